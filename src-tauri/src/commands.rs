@@ -0,0 +1,618 @@
+use std::{fs, path::Path, sync::Arc};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    config::{Config, ListenerConfig, ListenerKind},
+    har::{Har, HarOptions},
+    history::{ExchangeDetail, FrameRecord, HistoryPage},
+    http_util::{
+        intercept::{InterceptDecision, InterceptKind, PendingExchange},
+        request::RequestForFront,
+        tls::CertificateFormat,
+    },
+    project::{Project, ProjectInfo, TEMPORARY_PROJECT_DIR},
+    proxy::{
+        chain::UpstreamProxy,
+        filter::{InterceptFilter, InterceptRule},
+        listener::{ListenerInfo, ListenerManager},
+        repeater::RepeaterEntry,
+        replace::{ReplaceRule, Replacer},
+        reverse::ReverseRouter,
+        scope::{Scope, ScopeConfig},
+        upstream::{UpstreamClient, UpstreamConfig},
+        ProxyContext,
+    },
+};
+
+// ** certificate authority
+#[tauri::command]
+pub fn get_ca_certificate(context: State<'_, ProxyContext>) -> Result<String, String> {
+    let ca = context.ca.read().unwrap();
+    match ca.export(CertificateFormat::Pem, None) {
+        Ok(b) => Ok(String::from_utf8_lossy(&b).to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn export_ca_certificate(
+    path: String,
+    format: CertificateFormat,
+    password: Option<String>,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    let ca = context.ca.read().unwrap();
+    let b = match ca.export(format, password.as_deref()) {
+        Ok(b) => b,
+        Err(e) => return Err(e.to_string()),
+    };
+    match fs::write(path, b) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn rotate_ca_certificate(context: State<'_, ProxyContext>) -> Result<(), String> {
+    let mut ca = context.ca.write().unwrap();
+    match ca.rotate() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** listeners
+#[tauri::command]
+pub fn get_listeners(
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+) -> Result<Vec<ListenerInfo>, String> {
+    Ok(listeners.list(&context))
+}
+
+#[tauri::command]
+pub async fn add_listener(
+    address: String,
+    port: u16,
+    enabled: bool,
+    kind: Option<ListenerKind>,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ListenerConfig, String> {
+    let listener = ListenerConfig::new(address, port, enabled, kind.unwrap_or_default());
+    if let Err(e) = listener.socket_addr() {
+        return Err(e.to_string());
+    }
+    check_listener_kind(&listener.kind)?;
+    context
+        .config
+        .write()
+        .unwrap()
+        .listeners
+        .push(listener.clone());
+    if let Err(e) = context.save_config() {
+        return Err(e.to_string());
+    }
+    if listener.enabled {
+        if let Err(e) = listeners.start(&listener, context.inner().clone(), app_handle) {
+            return Err(e.to_string());
+        }
+    }
+    Ok(listener)
+}
+
+#[tauri::command]
+pub async fn remove_listener(
+    id: String,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+) -> Result<(), String> {
+    listeners.stop(&id).await;
+    context
+        .config
+        .write()
+        .unwrap()
+        .listeners
+        .retain(|l| l.id != id);
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn start_listener(
+    id: String,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    let listener = match context.config.read().unwrap().listener(&id) {
+        Some(l) => l.clone(),
+        None => return Err(format!("listener {} does not exist", id)),
+    };
+    match listeners.start(&listener, context.inner().clone(), app_handle) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn stop_listener(
+    id: String,
+    listeners: State<'_, ListenerManager>,
+) -> Result<(), String> {
+    listeners.stop(&id).await;
+    Ok(())
+}
+
+// ** change the address of a listener, a running one is rebound right away
+// * tauri passes every argument separately, the frontend already calls it this way
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn update_listener(
+    id: String,
+    address: String,
+    port: u16,
+    enabled: bool,
+    kind: Option<ListenerKind>,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ListenerConfig, String> {
+    if let Some(kind) = &kind {
+        check_listener_kind(kind)?;
+    }
+    let listener = {
+        let mut config = context.config.write().unwrap();
+        let listener = match config.listeners.iter_mut().find(|l| l.id == id) {
+            Some(l) => l,
+            None => return Err(format!("listener {} does not exist", id)),
+        };
+        listener.address = address;
+        listener.port = port;
+        listener.enabled = enabled;
+        // * left out, the listener keeps its kind
+        if let Some(kind) = kind {
+            listener.kind = kind;
+        }
+        listener.clone()
+    };
+    if let Err(e) = listener.socket_addr() {
+        return Err(e.to_string());
+    }
+    if let Err(e) = context.save_config() {
+        return Err(e.to_string());
+    }
+
+    listeners.stop(&id).await;
+    if listener.enabled {
+        if let Err(e) = listeners.start(&listener, context.inner().clone(), app_handle) {
+            return Err(e.to_string());
+        }
+    }
+    Ok(listener)
+}
+
+// * routes are compiled up front, so a bad upstream url is refused before it is saved
+fn check_listener_kind(kind: &ListenerKind) -> Result<(), String> {
+    if let ListenerKind::Reverse(reverse) = kind {
+        if let Err(e) = ReverseRouter::new(reverse) {
+            return Err(e.to_string());
+        }
+    }
+    Ok(())
+}
+
+// ** interception queue
+#[tauri::command]
+pub fn get_intercepted(context: State<'_, ProxyContext>) -> Result<Vec<PendingExchange>, String> {
+    Ok(context.intercept.pending())
+}
+
+#[tauri::command]
+pub fn reorder_intercepted(
+    pair_ids: Vec<String>,
+    context: State<'_, ProxyContext>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    context.intercept.reorder(&app_handle, &pair_ids);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn resolve_intercepted(
+    pair_id: String,
+    kind: InterceptKind,
+    decision: InterceptDecision,
+    context: State<'_, ProxyContext>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    match context
+        .intercept
+        .resolve(&app_handle, &pair_id, kind, decision)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_intercept_timeout(context: State<'_, ProxyContext>) -> Result<Option<u64>, String> {
+    Ok(context.config.read().unwrap().intercept_timeout_secs)
+}
+
+// ** `None` keeps held exchanges until the user decides
+#[tauri::command]
+pub fn set_intercept_timeout(
+    secs: Option<u64>,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    context.config.write().unwrap().intercept_timeout_secs = secs;
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_intercept_rules(context: State<'_, ProxyContext>) -> Result<Vec<InterceptRule>, String> {
+    Ok(context.config.read().unwrap().intercept_rules.clone())
+}
+
+// ** replaces the whole ordered list, nothing is stored unless every rule compiles
+#[tauri::command]
+pub fn set_intercept_rules(
+    rules: Vec<InterceptRule>,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    let filter = match InterceptFilter::new(&rules) {
+        Ok(f) => f,
+        Err(e) => return Err(e.to_string()),
+    };
+    context.config.write().unwrap().intercept_rules = rules;
+    *context.intercept_filter.write().unwrap() = filter;
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** scope
+#[tauri::command]
+pub fn get_scope(context: State<'_, ProxyContext>) -> Result<ScopeConfig, String> {
+    Ok(context.config.read().unwrap().scope.clone())
+}
+
+#[tauri::command]
+pub fn set_scope(scope: ScopeConfig, context: State<'_, ProxyContext>) -> Result<(), String> {
+    let compiled = match Scope::new(&scope) {
+        Ok(s) => s,
+        Err(e) => return Err(e.to_string()),
+    };
+    context.config.write().unwrap().scope = scope;
+    *context.scope.write().unwrap() = compiled;
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** match and replace
+#[tauri::command]
+pub fn get_replace_rules(context: State<'_, ProxyContext>) -> Result<Vec<ReplaceRule>, String> {
+    Ok(context.config.read().unwrap().replace_rules.clone())
+}
+
+// ** replaces the whole ordered list, nothing is stored unless every rule compiles
+#[tauri::command]
+pub fn set_replace_rules(
+    rules: Vec<ReplaceRule>,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    let replacer = match Replacer::new(&rules) {
+        Ok(r) => r,
+        Err(e) => return Err(e.to_string()),
+    };
+    context.config.write().unwrap().replace_rules = rules;
+    *context.replacer.write().unwrap() = Arc::new(replacer);
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** upstream client
+#[tauri::command]
+pub fn get_upstream_config(context: State<'_, ProxyContext>) -> Result<UpstreamConfig, String> {
+    Ok(context.config.read().unwrap().upstream.clone())
+}
+
+// * idle pooled connections of the previous client are dropped with it
+#[tauri::command]
+pub fn set_upstream_config(
+    upstream: UpstreamConfig,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    let client = match UpstreamClient::new(&upstream) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string()),
+    };
+    context.config.write().unwrap().upstream = upstream;
+    *context.upstream.write().unwrap() = Arc::new(client);
+    match context.save_config() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// * one GET through `proxy` alone, returns the status the destination answered with
+#[tauri::command]
+pub async fn test_upstream_proxy(
+    proxy: UpstreamProxy,
+    url: String,
+    context: State<'_, ProxyContext>,
+) -> Result<u16, String> {
+    let config = context.config.read().unwrap().upstream.clone();
+    let client = match UpstreamClient::through(&config, proxy) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string()),
+    };
+    let request = match hyper::Request::get(url).body(hyper::Body::empty()) {
+        Ok(r) => r,
+        Err(e) => return Err(e.to_string()),
+    };
+    match client.send(request).await {
+        Ok((rs, _)) => Ok(rs.status().as_u16()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** history
+#[tauri::command]
+pub fn get_history(
+    offset: u64,
+    limit: u64,
+    context: State<'_, ProxyContext>,
+) -> Result<HistoryPage, String> {
+    match context.history().list(offset, limit) {
+        Ok(p) => Ok(p),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_history_entry(
+    id: i64,
+    context: State<'_, ProxyContext>,
+) -> Result<ExchangeDetail, String> {
+    match context.history().get(id) {
+        Ok(Some(d)) => Ok(d),
+        Ok(None) => Err(format!("history entry {} does not exist", id)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// * `pair_id` of the upgrade handshake
+#[tauri::command]
+pub fn get_websocket_frames(
+    pair_id: String,
+    context: State<'_, ProxyContext>,
+) -> Result<Vec<FrameRecord>, String> {
+    match context.history().frames(&pair_id) {
+        Ok(f) => Ok(f),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn delete_history_entries(
+    ids: Vec<i64>,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    match context.history().delete(&ids) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn clear_history(context: State<'_, ProxyContext>) -> Result<(), String> {
+    match context.history().clear() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// * `ids` picks a subset of the history, everything is exported without it
+#[tauri::command]
+pub fn export_har(
+    path: String,
+    ids: Option<Vec<i64>>,
+    include_bodies: bool,
+    context: State<'_, ProxyContext>,
+) -> Result<(), String> {
+    let exchanges = match ids {
+        Some(ids) => context.history().selected(&ids),
+        None => context.history().all(),
+    };
+    let exchanges = match exchanges {
+        Ok(e) => e,
+        Err(e) => return Err(e.to_string()),
+    };
+    let har = match Har::from_exchanges(&exchanges, &HarOptions { include_bodies }) {
+        Ok(h) => h,
+        Err(e) => return Err(e.to_string()),
+    };
+    let json = match serde_json::to_string_pretty(&har) {
+        Ok(j) => j,
+        Err(e) => return Err(e.to_string()),
+    };
+    match fs::write(path, json) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// * returns how many exchanges were added
+#[tauri::command]
+pub fn import_har(path: String, context: State<'_, ProxyContext>) -> Result<usize, String> {
+    let json = match fs::read_to_string(path) {
+        Ok(j) => j,
+        Err(e) => return Err(e.to_string()),
+    };
+    let har: Har = match serde_json::from_str(&json) {
+        Ok(h) => h,
+        Err(e) => return Err(e.to_string()),
+    };
+    match har.import_into(&context.history()) {
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ** repeater
+#[tauri::command]
+pub async fn repeater_send(
+    tab: String,
+    request: RequestForFront,
+    context: State<'_, ProxyContext>,
+) -> Result<RepeaterEntry, String> {
+    let upstream = context.upstream();
+    match context.repeater.send(&upstream, &tab, request).await {
+        Ok(e) => Ok(e),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn get_repeater_history(
+    tab: String,
+    context: State<'_, ProxyContext>,
+) -> Result<Vec<RepeaterEntry>, String> {
+    Ok(context.repeater.history(&tab))
+}
+
+#[tauri::command]
+pub fn close_repeater_tab(tab: String, context: State<'_, ProxyContext>) -> Result<(), String> {
+    context.repeater.close(&tab);
+    Ok(())
+}
+
+// ** projects
+const PROJECT_CHANGED_EVENT: &str = "project-changed";
+
+#[tauri::command]
+pub fn get_project(context: State<'_, ProxyContext>) -> Result<ProjectInfo, String> {
+    Ok(context.project.read().unwrap().info())
+}
+
+// ** a new project starts from the app defaults, not from the settings of the open one
+#[tauri::command]
+pub async fn new_project(
+    path: String,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ProjectInfo, String> {
+    let defaults = match Config::load(&context.config_path) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string()),
+    };
+    let project = match Project::create(Path::new(&path), &defaults) {
+        Ok(p) => p,
+        Err(e) => return Err(e.to_string()),
+    };
+    switch_project(project, defaults, &context, &listeners, &app_handle).await
+}
+
+#[tauri::command]
+pub async fn open_project(
+    path: String,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ProjectInfo, String> {
+    let project = match Project::open(Path::new(&path)) {
+        Ok(p) => p,
+        Err(e) => return Err(e.to_string()),
+    };
+    let config = match project.load_config() {
+        Ok(Some(c)) => c,
+        Ok(None) => match Config::load(&context.config_path) {
+            Ok(c) => c,
+            Err(e) => return Err(e.to_string()),
+        },
+        Err(e) => return Err(e.to_string()),
+    };
+    switch_project(project, config, &context, &listeners, &app_handle).await
+}
+
+// ** the copy becomes the open project, a temporary one is discarded
+#[tauri::command]
+pub async fn save_project_as(
+    path: String,
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ProjectInfo, String> {
+    if let Err(e) = context.save_config() {
+        return Err(e.to_string());
+    }
+    let project = match context.project.read().unwrap().save_as(Path::new(&path)) {
+        Ok(p) => p,
+        Err(e) => return Err(e.to_string()),
+    };
+    let config = context.config.read().unwrap().clone();
+    switch_project(project, config, &context, &listeners, &app_handle).await
+}
+
+#[tauri::command]
+pub async fn close_project(
+    context: State<'_, ProxyContext>,
+    listeners: State<'_, ListenerManager>,
+    app_handle: AppHandle,
+) -> Result<ProjectInfo, String> {
+    let defaults = match Config::load(&context.config_path) {
+        Ok(c) => c,
+        Err(e) => return Err(e.to_string()),
+    };
+    let temporary_dir = match app_handle.path_resolver().app_data_dir() {
+        Some(d) => d.join(TEMPORARY_PROJECT_DIR),
+        None => return Err("failed to resolve app data directory".to_string()),
+    };
+    let project = match Project::temporary(&temporary_dir, &defaults) {
+        Ok(p) => p,
+        Err(e) => return Err(e.to_string()),
+    };
+    switch_project(project, defaults, &context, &listeners, &app_handle).await
+}
+
+// ** listeners are restarted because the new project may bind different addresses
+async fn switch_project(
+    project: Project,
+    config: Config,
+    context: &ProxyContext,
+    listeners: &ListenerManager,
+    app_handle: &AppHandle,
+) -> Result<ProjectInfo, String> {
+    if let Err(e) = context.apply_config(config) {
+        // * the new file is not used, a temporary one must not be left behind
+        if let Err(e) = project.close() {
+            println!("failed to discard project: {}", e);
+        }
+        return Err(e.to_string());
+    }
+    let info = project.info();
+    listeners.stop_all().await;
+    let previous = std::mem::replace(&mut *context.project.write().unwrap(), project);
+    if let Err(e) = previous.close() {
+        println!("failed to close project: {}", e);
+    }
+    listeners.start_enabled(context, app_handle);
+    if let Err(e) = app_handle.emit_all(PROJECT_CHANGED_EVENT, info.clone()) {
+        println!("failed to emit project change: {}", e);
+    }
+    Ok(info)
+}
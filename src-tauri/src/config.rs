@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::proxy::{
+    filter::InterceptRule, replace::ReplaceRule, reverse::ReverseConfig, scope::ScopeConfig,
+    upstream::UpstreamConfig,
+};
+
+pub const CONFIG_FILE_NAME: &str = "config.json";
+const DEFAULT_LISTENER_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_LISTENER_PORT: u16 = 8080;
+const DEFAULT_INTERCEPT_TIMEOUT_SECS: u64 = 300;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(" >>> failed to load config >>> `{0}`")]
+    ConfigLoadError(String),
+    #[error(" >>> failed to save config >>> `{0}`")]
+    ConfigSaveError(String),
+    #[error(" >>> invalid listener address >>> `{0}`")]
+    InvalidListenerAddressError(String),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub listeners: Vec<ListenerConfig>,
+    // ** held exchanges are forwarded as they are after this long, `None` waits forever
+    #[serde(default = "default_intercept_timeout_secs")]
+    pub intercept_timeout_secs: Option<u64>,
+    // ** what the pilot holds, the pilot toggle stays the master switch over all of them
+    // ** with no enabled rule nothing is held, even while the pilot is on
+    #[serde(default)]
+    pub intercept_rules: Vec<InterceptRule>,
+    #[serde(default)]
+    pub scope: ScopeConfig,
+    #[serde(default)]
+    pub replace_rules: Vec<ReplaceRule>,
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+}
+
+fn default_intercept_timeout_secs() -> Option<u64> {
+    Some(DEFAULT_INTERCEPT_TIMEOUT_SECS)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listeners: vec![ListenerConfig::new(
+                DEFAULT_LISTENER_ADDRESS.to_string(),
+                DEFAULT_LISTENER_PORT,
+                true,
+                ListenerKind::Http,
+            )],
+            intercept_timeout_secs: default_intercept_timeout_secs(),
+            intercept_rules: Vec::new(),
+            scope: ScopeConfig::default(),
+            replace_rules: Vec::new(),
+            upstream: UpstreamConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // ** a missing file is not an error, the defaults are used until the first save
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let json = match fs::read_to_string(path) {
+            Ok(j) => j,
+            Err(e) => return Err(ConfigError::ConfigLoadError(e.to_string())),
+        };
+        match serde_json::from_str(&json) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(ConfigError::ConfigLoadError(e.to_string())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(ConfigError::ConfigSaveError(e.to_string()));
+            }
+        }
+        let json = match serde_json::to_string_pretty(self) {
+            Ok(j) => j,
+            Err(e) => return Err(ConfigError::ConfigSaveError(e.to_string())),
+        };
+        match fs::write(path, json) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ConfigError::ConfigSaveError(e.to_string())),
+        }
+    }
+
+    pub fn intercept_timeout(&self) -> Option<Duration> {
+        self.intercept_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn listener(&self, id: &str) -> Option<&ListenerConfig> {
+        self.listeners.iter().find(|l| l.id == id)
+    }
+}
+
+// ** what a listener speaks to its clients
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListenerKind {
+    // * forward proxy, absolute-form requests and CONNECT
+    #[default]
+    Http,
+    // * without a username any client is let in
+    Socks5 {
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    // * linux only, takes connections redirected with iptables REDIRECT or TPROXY
+    Transparent,
+    // * origin-form requests rewritten onto configured upstreams, no browser proxy setting needed
+    Reverse(ReverseConfig),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListenerConfig {
+    pub id: String,
+    pub address: String,
+    pub port: u16,
+    pub enabled: bool,
+    #[serde(default)]
+    pub kind: ListenerKind,
+}
+
+impl ListenerConfig {
+    pub fn new(address: String, port: u16, enabled: bool, kind: ListenerKind) -> Self {
+        ListenerConfig {
+            id: uuid::Uuid::new_v4().to_string(),
+            address,
+            port,
+            enabled,
+            kind,
+        }
+    }
+
+    // ** `address` is a bare ip, v4 or v6, without brackets
+    pub fn socket_addr(&self) -> Result<SocketAddr, ConfigError> {
+        match self.address.parse::<IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, self.port)),
+            Err(e) => Err(ConfigError::InvalidListenerAddressError(format!(
+                "{}: {}",
+                self.address, e
+            ))),
+        }
+    }
+}
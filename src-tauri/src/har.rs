@@ -0,0 +1,791 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::history::{ExchangeDetail, HistoryStore, RecordedRequest, RecordedResponse};
+use crate::http_util::{
+    body::{body_from_front, body_to_front, BodyEncoding},
+    config::PAIR_ID_HEADER_NAME,
+    header::{pair_id_string, HeaderEntry},
+    request::RequestForFront,
+    response::ResponseForFront,
+};
+
+const HAR_VERSION: &str = "1.2";
+const CREATOR_NAME: &str = "rsproxy";
+
+#[derive(Error, Debug)]
+pub enum HarError {
+    #[error(" >>> failed to export har >>> `{0}`")]
+    ExportError(String),
+    #[error(" >>> failed to import har >>> `{0}`")]
+    ImportError(String),
+}
+
+// ** the subset of HAR 1.2 that a proxy can fill, see http://www.softwareishard.com/blog/har-12-spec/
+#[derive(Serialize, Deserialize)]
+pub struct Har {
+    pub log: Log,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Log {
+    pub version: String,
+    pub creator: Creator,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Creator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub started_date_time: String,
+    pub time: f64,
+    pub request: Request,
+    pub response: Response,
+    #[serde(default)]
+    pub cache: Cache,
+    pub timings: Timings,
+    #[serde(
+        default,
+        rename = "serverIPAddress",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub server_ip_address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Cookie>,
+    #[serde(default)]
+    pub headers: Vec<NameValue>,
+    #[serde(default)]
+    pub query_string: Vec<NameValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<PostData>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<Cookie>,
+    #[serde(default)]
+    pub headers: Vec<NameValue>,
+    pub content: Content,
+    #[serde(default, rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NameValue {
+    pub name: String,
+    pub value: String,
+}
+
+// ** `_encoding` is not in the spec, custom fields start with an underscore
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+    #[serde(
+        default,
+        rename = "_encoding",
+        alias = "encoding",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Cache {}
+
+// ** -1 or left out means the phase does not apply, fields other tools add are kept as they are
+#[derive(Serialize, Deserialize)]
+pub struct Timings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect: Option<f64>,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+    // * already part of `connect`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssl: Option<f64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Timings {
+    // * the total of an entry, ssl is counted within connect
+    fn total(&self) -> f64 {
+        [self.blocked, self.dns, self.connect]
+            .iter()
+            .flatten()
+            .chain([self.send, self.wait, self.receive].iter())
+            .filter(|t| **t > 0.0)
+            .sum()
+    }
+}
+
+pub struct HarOptions {
+    pub include_bodies: bool,
+}
+
+impl Har {
+    pub fn from_exchanges(
+        exchanges: &[ExchangeDetail],
+        options: &HarOptions,
+    ) -> Result<Self, HarError> {
+        let mut entries = Vec::new();
+        for exchange in exchanges {
+            entries.push(Entry::from_exchange(exchange, options)?);
+        }
+        Ok(Har {
+            log: Log {
+                version: HAR_VERSION.to_string(),
+                creator: Creator {
+                    name: CREATOR_NAME.to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries,
+            },
+        })
+    }
+
+    // ** every entry becomes a finished exchange, as if the proxy had seen it
+    pub fn import_into(&self, history: &HistoryStore) -> Result<usize, HarError> {
+        let mut imported = Vec::new();
+        for entry in &self.log.entries {
+            imported.push(entry.to_imported()?);
+        }
+        let exchanges: Vec<(RecordedRequest, Option<RecordedResponse>)> = imported
+            .iter()
+            .map(|i| {
+                let rq = RecordedRequest {
+                    pair_id: &i.pair_id,
+                    method: &i.method,
+                    url: &i.url,
+                    request: &i.request,
+                    client_addr: None,
+                    request_size: i.request_size,
+                    started_at: i.started_at,
+                };
+                let rs = i.response.as_ref().map(|r| RecordedResponse {
+                    pair_id: &i.pair_id,
+                    status: r.status,
+                    response: &r.response,
+                    upstream_addr: r.upstream_addr.clone(),
+                    connection_reused: None,
+                    response_size: r.response_size,
+                    finished_at: r.finished_at,
+                    timings: Some(&i.timings),
+                });
+                (rq, rs)
+            })
+            .collect();
+        match history.import(&exchanges) {
+            Ok(n) => Ok(n),
+            Err(e) => Err(HarError::ImportError(e.to_string())),
+        }
+    }
+}
+
+struct ImportedExchange {
+    pair_id: String,
+    method: String,
+    url: String,
+    request: String,
+    request_size: u64,
+    started_at: i64,
+    timings: String,
+    response: Option<ImportedResponse>,
+}
+
+struct ImportedResponse {
+    status: u16,
+    response: String,
+    upstream_addr: Option<String>,
+    response_size: u64,
+    finished_at: i64,
+}
+
+impl Entry {
+    fn from_exchange(exchange: &ExchangeDetail, options: &HarOptions) -> Result<Self, HarError> {
+        let summary = &exchange.summary;
+        let rq: RequestForFront = match serde_json::from_str(&exchange.request) {
+            Ok(r) => r,
+            Err(e) => return Err(HarError::ExportError(e.to_string())),
+        };
+        let rs: Option<ResponseForFront> = match &exchange.response {
+            Some(r) => match serde_json::from_str(r) {
+                Ok(r) => Some(r),
+                Err(e) => return Err(HarError::ExportError(e.to_string())),
+            },
+            None => None,
+        };
+
+        let started =
+            match OffsetDateTime::from_unix_timestamp_nanos(summary.started_at as i128 * 1_000_000)
+            {
+                Ok(t) => t,
+                Err(e) => return Err(HarError::ExportError(e.to_string())),
+            };
+        let started_date_time = match started.format(&Rfc3339) {
+            Ok(t) => t,
+            Err(e) => return Err(HarError::ExportError(e.to_string())),
+        };
+        // * a proxied exchange only has the whole round trip, it is reported as waiting
+        let timings = match &exchange.timings {
+            Some(t) => match serde_json::from_str::<Timings>(t) {
+                Ok(t) => t,
+                Err(e) => return Err(HarError::ExportError(e.to_string())),
+            },
+            None => Timings {
+                blocked: None,
+                dns: None,
+                connect: None,
+                send: 0.0,
+                wait: match summary.finished_at {
+                    Some(f) => (f - summary.started_at).max(0) as f64,
+                    None => 0.0,
+                },
+                receive: 0.0,
+                ssl: None,
+                extra: serde_json::Map::new(),
+            },
+        };
+        let time = timings.total();
+
+        let request = Request::from_front(&rq, summary.request_size, options)?;
+        let response = match &rs {
+            Some(rs) => Response::from_front(rs, summary.response_size, options)?,
+            // * HAR has no place for a missing response, status 0 is what browsers write
+            None => Response::empty(),
+        };
+
+        Ok(Entry {
+            started_date_time,
+            time,
+            request,
+            response,
+            cache: Cache {},
+            timings,
+            server_ip_address: summary.upstream_addr.as_ref().map(|a| ip_of(a).to_string()),
+        })
+    }
+
+    fn to_imported(&self) -> Result<ImportedExchange, HarError> {
+        let started = match OffsetDateTime::parse(&self.started_date_time, &Rfc3339) {
+            Ok(t) => t,
+            Err(e) => return Err(HarError::ImportError(e.to_string())),
+        };
+        let started_at = (started.unix_timestamp_nanos() / 1_000_000) as i64;
+        let pair_id = pair_id_string(&uuid::Uuid::new_v4());
+        let timings = match serde_json::to_string(&self.timings) {
+            Ok(t) => t,
+            Err(e) => return Err(HarError::ImportError(e.to_string())),
+        };
+
+        let rq = self.request.to_front()?;
+        let request_size = match self.request.body_size {
+            s if s >= 0 => s as u64,
+            _ => body_len(&rq.body, rq.body_encoding)?,
+        };
+        let request = match serde_json::to_string(&rq) {
+            Ok(r) => r,
+            Err(e) => return Err(HarError::ImportError(e.to_string())),
+        };
+
+        // * status 0 is how HAR writes a request that never got an answer
+        let response = if self.response.status == 0 {
+            None
+        } else {
+            let rs = self.response.to_front()?;
+            let response_size = match (self.response.body_size, self.response.content.size) {
+                (s, _) if s >= 0 => s as u64,
+                (_, s) if s >= 0 => s as u64,
+                _ => body_len(&rs.body, rs.body_encoding)?,
+            };
+            let response = match serde_json::to_string(&rs) {
+                Ok(r) => r,
+                Err(e) => return Err(HarError::ImportError(e.to_string())),
+            };
+            Some(ImportedResponse {
+                status: rs.status,
+                response,
+                upstream_addr: self.server_ip_address.clone(),
+                response_size,
+                finished_at: started_at + self.time.max(0.0).round() as i64,
+            })
+        };
+
+        Ok(ImportedExchange {
+            pair_id,
+            method: rq.method.clone(),
+            url: rq.url.clone(),
+            request,
+            request_size,
+            started_at,
+            timings,
+            response,
+        })
+    }
+}
+
+impl Request {
+    fn to_front(&self) -> Result<RequestForFront, HarError> {
+        let (body, body_encoding) = match &self.post_data {
+            Some(p) if p.text.is_empty() && !p.params.is_empty() => (
+                p.params
+                    .iter()
+                    .map(|p| format!("{}={}", form_encode(&p.name), form_encode(&p.value)))
+                    .collect::<Vec<String>>()
+                    .join("&"),
+                BodyEncoding::Utf8,
+            ),
+            Some(p) => body_to_front_from_har(&p.text, p.encoding.as_deref())?,
+            None => (String::new(), BodyEncoding::Utf8),
+        };
+        Ok(RequestForFront {
+            headers: headers_to_front(&self.headers)?,
+            url: self.url.clone(),
+            method: self.method.clone(),
+            version: version_from_har(&self.http_version),
+            body,
+            body_encoding,
+        })
+    }
+
+    fn from_front(rq: &RequestForFront, size: u64, options: &HarOptions) -> Result<Self, HarError> {
+        let headers = headers_from_front(&rq.headers)?;
+        let cookies = headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("cookie"))
+            .flat_map(|h| h.value.split(';'))
+            .filter_map(cookie_pair)
+            .collect();
+        let post_data = if options.include_bodies && !rq.body.is_empty() {
+            Some(PostData {
+                mime_type: header_value(&headers, "content-type"),
+                text: rq.body.clone(),
+                params: Vec::new(),
+                encoding: encoding_name(rq.body_encoding),
+            })
+        } else {
+            None
+        };
+        Ok(Request {
+            method: rq.method.clone(),
+            url: rq.url.clone(),
+            http_version: rq.version.clone(),
+            cookies,
+            query_string: query_string(&rq.url),
+            headers,
+            post_data,
+            headers_size: -1,
+            body_size: size as i64,
+        })
+    }
+}
+
+impl Response {
+    fn from_front(
+        rs: &ResponseForFront,
+        size: Option<u64>,
+        options: &HarOptions,
+    ) -> Result<Self, HarError> {
+        let headers = headers_from_front(&rs.headers)?;
+        let cookies = headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("set-cookie"))
+            .filter_map(|h| h.value.split(';').next().and_then(cookie_pair))
+            .collect();
+        // * the stored body is already decoded, `size` is what came over the wire
+        let decoded = match body_from_front(&rs.body, rs.body_encoding) {
+            Ok(b) => b,
+            Err(e) => return Err(HarError::ExportError(e.to_string())),
+        };
+        let (text, encoding) = if options.include_bodies {
+            (Some(rs.body.clone()), encoding_name(rs.body_encoding))
+        } else {
+            (None, None)
+        };
+        Ok(Response {
+            status: rs.status,
+            status_text: hyper::StatusCode::from_u16(rs.status)
+                .ok()
+                .and_then(|s| s.canonical_reason())
+                .unwrap_or("")
+                .to_string(),
+            http_version: rs.version.clone(),
+            cookies,
+            content: Content {
+                size: decoded.len() as i64,
+                mime_type: header_value(&headers, "content-type"),
+                text,
+                encoding,
+            },
+            redirect_url: header_value(&headers, "location"),
+            headers,
+            headers_size: -1,
+            body_size: size.map(|s| s as i64).unwrap_or(-1),
+        })
+    }
+
+    fn to_front(&self) -> Result<ResponseForFront, HarError> {
+        let (body, body_encoding) = match &self.content.text {
+            Some(t) => body_to_front_from_har(t, self.content.encoding.as_deref())?,
+            None => (String::new(), BodyEncoding::Utf8),
+        };
+        Ok(ResponseForFront {
+            headers: headers_to_front(&self.headers)?,
+            body,
+            body_encoding,
+            status: self.status,
+            version: version_from_har(&self.http_version),
+        })
+    }
+
+    fn empty() -> Self {
+        Response {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: Content {
+                size: 0,
+                mime_type: String::new(),
+                text: None,
+                encoding: None,
+            },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+        }
+    }
+}
+
+// ** header values that are not text are written as lossy text, HAR headers are plain strings
+fn headers_from_front(json: &str) -> Result<Vec<NameValue>, HarError> {
+    let entries: Vec<HeaderEntry> = match serde_json::from_str(json) {
+        Ok(e) => e,
+        Err(e) => return Err(HarError::ExportError(e.to_string())),
+    };
+    let mut headers = Vec::new();
+    for entry in entries {
+        if entry.name.eq_ignore_ascii_case(PAIR_ID_HEADER_NAME) {
+            continue;
+        }
+        let value = match body_from_front(&entry.value, entry.value_encoding) {
+            Ok(v) => String::from_utf8_lossy(&v).to_string(),
+            Err(e) => return Err(HarError::ExportError(e.to_string())),
+        };
+        headers.push(NameValue {
+            name: entry.name,
+            value,
+        });
+    }
+    Ok(headers)
+}
+
+// ** repeated names stay separate entries, http/2 pseudo headers are not real headers
+fn headers_to_front(headers: &[NameValue]) -> Result<String, HarError> {
+    let entries: Vec<HeaderEntry> = headers
+        .iter()
+        .filter(|h| !h.name.starts_with(':'))
+        .filter(|h| !h.name.eq_ignore_ascii_case(PAIR_ID_HEADER_NAME))
+        .map(|h| HeaderEntry {
+            name: h.name.clone(),
+            value: h.value.clone(),
+            value_encoding: BodyEncoding::Utf8,
+        })
+        .collect();
+    match serde_json::to_string(&entries) {
+        Ok(h) => Ok(h),
+        Err(e) => Err(HarError::ImportError(e.to_string())),
+    }
+}
+
+// * base64 bodies that turn out to be text are stored as text, like captured ones
+fn body_to_front_from_har(
+    text: &str,
+    encoding: Option<&str>,
+) -> Result<(String, BodyEncoding), HarError> {
+    match encoding {
+        Some(e) if e.eq_ignore_ascii_case("base64") => {
+            match body_from_front(text, BodyEncoding::Base64) {
+                Ok(b) => Ok(body_to_front(b)),
+                Err(e) => Err(HarError::ImportError(e.to_string())),
+            }
+        }
+        Some(e) => Err(HarError::ImportError(format!(
+            "content encoding {} is not supported",
+            e
+        ))),
+        None => Ok((text.to_string(), BodyEncoding::Utf8)),
+    }
+}
+
+// ** params hold the decoded values, the body they came from was application/x-www-form-urlencoded
+fn form_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                encoded.push(b as char)
+            }
+            b' ' => encoded.push('+'),
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn body_len(body: &str, encoding: BodyEncoding) -> Result<u64, HarError> {
+    match body_from_front(body, encoding) {
+        Ok(b) => Ok(b.len() as u64),
+        Err(e) => Err(HarError::ImportError(e.to_string())),
+    }
+}
+
+// ** browsers write `h2`, `http/2.0` or `HTTP/1.1`, the rest of the app only knows the last form
+fn version_from_har(version: &str) -> String {
+    match version.to_ascii_lowercase().as_str() {
+        "http/0.9" => "HTTP/0.9",
+        "http/1.0" => "HTTP/1.0",
+        "h2" | "h2c" | "http/2" | "http/2.0" => "HTTP/2.0",
+        "h3" | "http/3" | "http/3.0" => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+    .to_string()
+}
+
+fn header_value(headers: &[NameValue], name: &str) -> String {
+    match headers.iter().find(|h| h.name.eq_ignore_ascii_case(name)) {
+        Some(h) => h.value.clone(),
+        None => String::new(),
+    }
+}
+
+fn query_string(url: &str) -> Vec<NameValue> {
+    let query = match url.split_once('?') {
+        Some((_, q)) => q.split('#').next().unwrap_or(""),
+        None => return Vec::new(),
+    };
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((n, v)) => NameValue {
+                name: n.to_string(),
+                value: v.to_string(),
+            },
+            None => NameValue {
+                name: p.to_string(),
+                value: String::new(),
+            },
+        })
+        .collect()
+}
+
+fn cookie_pair(pair: &str) -> Option<Cookie> {
+    let (name, value) = pair.trim().split_once('=')?;
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn encoding_name(encoding: BodyEncoding) -> Option<String> {
+    match encoding {
+        BodyEncoding::Utf8 => None,
+        BodyEncoding::Base64 => Some("base64".to_string()),
+    }
+}
+
+// ** `1.2.3.4:443` or `[::1]:443` to the bare address
+fn ip_of(addr: &str) -> &str {
+    match addr.rsplit_once(':') {
+        Some((ip, _)) => ip.trim_start_matches('[').trim_end_matches(']'),
+        None => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::ExchangeSummary;
+
+    const HAR: &str = r#"{"log": {"version": "1.2", "creator": {"name": "browser", "version": "1"},
+        "entries": [{
+            "startedDateTime": "2024-05-01T10:00:00.000Z",
+            "time": 42.5,
+            "request": {
+                "method": "POST", "url": "http://example.com/form?x=1", "httpVersion": "h2",
+                "headers": [
+                    {"name": "x-tag", "value": "one"},
+                    {"name": "content-type", "value": "application/x-www-form-urlencoded"},
+                    {"name": "x-tag", "value": "two"}
+                ],
+                "postData": {"mimeType": "application/x-www-form-urlencoded", "text": "a=1&b=%26"},
+                "headersSize": -1, "bodySize": 9
+            },
+            "response": {
+                "status": 200, "statusText": "OK", "httpVersion": "h2",
+                "headers": [
+                    {"name": "set-cookie", "value": "a=1"},
+                    {"name": "set-cookie", "value": "b=2"},
+                    {"name": "content-type", "value": "image/png"}
+                ],
+                "content": {"size": 4, "mimeType": "image/png", "text": "AJ+Slg==", "encoding": "base64"},
+                "redirectURL": "", "headersSize": -1, "bodySize": 4
+            },
+            "cache": {},
+            "timings": {"blocked": -1, "dns": 3.5, "connect": 10, "ssl": 6, "send": 1, "wait": 25, "receive": 3, "_queued": 0.25}
+        }]}}"#;
+
+    // * what the history would hand back for an imported exchange
+    fn detail(imported: ImportedExchange) -> ExchangeDetail {
+        let response = imported.response.as_ref();
+        ExchangeDetail {
+            summary: ExchangeSummary {
+                id: 1,
+                pair_id: imported.pair_id.clone(),
+                method: imported.method.clone(),
+                url: imported.url.clone(),
+                status: response.map(|r| r.status),
+                error: None,
+                client_addr: None,
+                upstream_addr: response.and_then(|r| r.upstream_addr.clone()),
+                request_size: imported.request_size,
+                response_size: response.map(|r| r.response_size),
+                started_at: imported.started_at,
+                finished_at: response.map(|r| r.finished_at),
+                connection_reused: None,
+            },
+            request: imported.request,
+            response: response.map(|r| r.response.clone()),
+            timings: Some(imported.timings),
+        }
+    }
+
+    fn round_trip(har: &str) -> Entry {
+        let har: Har = serde_json::from_str(har).unwrap();
+        let imported = har.log.entries[0].to_imported().unwrap();
+        let options = HarOptions {
+            include_bodies: true,
+        };
+        let mut exported = Har::from_exchanges(&[detail(imported)], &options).unwrap();
+        exported.log.entries.remove(0)
+    }
+
+    fn values<'a>(headers: &'a [NameValue], name: &str) -> Vec<&'a str> {
+        headers
+            .iter()
+            .filter(|h| h.name == name)
+            .map(|h| h.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn export_gives_back_what_was_imported() {
+        let entry = round_trip(HAR);
+        assert_eq!(entry.started_date_time, "2024-05-01T10:00:00Z");
+        assert_eq!(entry.request.method, "POST");
+        assert_eq!(entry.request.http_version, "HTTP/2.0");
+        assert_eq!(values(&entry.request.headers, "x-tag"), ["one", "two"]);
+        let post_data = entry.request.post_data.unwrap();
+        assert_eq!(post_data.text, "a=1&b=%26");
+        assert_eq!(post_data.encoding, None);
+        assert_eq!(
+            values(&entry.response.headers, "set-cookie"),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(entry.response.cookies.len(), 2);
+        assert_eq!(entry.response.content.size, 4);
+        assert_eq!(entry.response.content.text.as_deref(), Some("AJ+Slg=="));
+        assert_eq!(entry.response.content.encoding.as_deref(), Some("base64"));
+        assert_eq!(entry.response.body_size, 4);
+    }
+
+    #[test]
+    fn timings_survive_a_round_trip() {
+        let original: serde_json::Value = serde_json::from_str(HAR).unwrap();
+        let entry = round_trip(HAR);
+        let timings = serde_json::to_value(&entry.timings).unwrap();
+        let timings = timings.as_object().unwrap();
+        let original = original["log"]["entries"][0]["timings"]
+            .as_object()
+            .unwrap();
+        assert_eq!(timings.len(), original.len());
+        for (name, value) in original {
+            assert_eq!(timings[name].as_f64(), value.as_f64(), "{name}");
+        }
+        // * the sum of the phases that applied, ssl is inside connect
+        assert_eq!(entry.time, 42.5);
+    }
+
+    #[test]
+    fn params_without_text_become_an_encoded_form() {
+        let har = HAR.replace(
+            r#""text": "a=1&b=%26"}"#,
+            r#""text": "", "params": [{"name": "q", "value": "a b&c=d"}, {"name": "é", "value": ""}]}"#,
+        );
+        let har: Har = serde_json::from_str(&har).unwrap();
+        let rq = har.log.entries[0].request.to_front().unwrap();
+        assert_eq!(rq.body, "q=a+b%26c%3Dd&%C3%A9=");
+    }
+}
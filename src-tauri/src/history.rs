@@ -0,0 +1,588 @@
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Params, Row};
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error(" >>> failed to open history >>> `{0}`")]
+    OpenError(String),
+    #[error(" >>> failed to write history >>> `{0}`")]
+    WriteError(String),
+    #[error(" >>> failed to read history >>> `{0}`")]
+    ReadError(String),
+    #[error(" >>> history task failed >>> `{0}`")]
+    TaskError(String),
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS exchanges (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair_id TEXT NOT NULL UNIQUE,
+    method TEXT NOT NULL,
+    url TEXT NOT NULL,
+    status INTEGER,
+    request TEXT NOT NULL,
+    response TEXT,
+    error TEXT,
+    client_addr TEXT,
+    upstream_addr TEXT,
+    request_size INTEGER NOT NULL,
+    response_size INTEGER,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER,
+    connection_reused INTEGER,
+    timings TEXT
+);
+CREATE TABLE IF NOT EXISTS websocket_frames (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair_id TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    opcode TEXT NOT NULL,
+    frame TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS websocket_frames_pair_id ON websocket_frames (pair_id);
+CREATE TABLE IF NOT EXISTS settings (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    config TEXT NOT NULL
+);
+";
+
+// ** columns added after a table was first created, older project files get them when opened
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("exchanges", "connection_reused", "INTEGER"),
+    ("exchanges", "timings", "TEXT"),
+];
+
+// * older sqlite builds refuse statements with more than 999 parameters
+const SELECTED_CHUNK_SIZE: usize = 500;
+
+const SUMMARY_COLUMNS: &str = "id, pair_id, method, url, status, error, client_addr, upstream_addr, request_size, response_size, started_at, finished_at, connection_reused";
+
+// ** one row of the history list, without the messages themselves
+#[derive(Serialize)]
+pub struct ExchangeSummary {
+    pub id: i64,
+    pub pair_id: String,
+    pub method: String,
+    pub url: String,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub client_addr: Option<String>,
+    pub upstream_addr: Option<String>,
+    pub request_size: u64,
+    pub response_size: Option<u64>,
+    // * unix milliseconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    // * `None` when the response did not come from the upstream client
+    pub connection_reused: Option<bool>,
+}
+
+// ** `request` and `response` are the RequestForFront / ResponseForFront json the frontend already knows
+#[derive(Serialize)]
+pub struct ExchangeDetail {
+    #[serde(flatten)]
+    pub summary: ExchangeSummary,
+    pub request: String,
+    pub response: Option<String>,
+    // * the HAR timings object of an imported exchange, as it was in the file
+    pub timings: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    pub total: u64,
+    pub items: Vec<ExchangeSummary>,
+}
+
+pub struct RecordedRequest<'a> {
+    pub pair_id: &'a str,
+    pub method: &'a str,
+    pub url: &'a str,
+    pub request: &'a str,
+    pub client_addr: Option<String>,
+    pub request_size: u64,
+    pub started_at: i64,
+}
+
+pub struct RecordedResponse<'a> {
+    pub pair_id: &'a str,
+    pub status: u16,
+    pub response: &'a str,
+    pub upstream_addr: Option<String>,
+    pub connection_reused: Option<bool>,
+    pub response_size: u64,
+    pub finished_at: i64,
+    // * only imported exchanges have them, see ExchangeDetail
+    pub timings: Option<&'a str>,
+}
+
+pub struct RecordedFrame<'a> {
+    pub pair_id: &'a str,
+    pub direction: &'a str,
+    pub opcode: &'a str,
+    pub frame: &'a str,
+    pub timestamp: i64,
+}
+
+// ** `frame` is the FrameForFront json, the frames of a websocket hang off its handshake's pair id
+#[derive(Serialize)]
+pub struct FrameRecord {
+    pub id: i64,
+    pub pair_id: String,
+    pub direction: String,
+    pub opcode: String,
+    pub frame: String,
+    // * unix milliseconds
+    pub timestamp: i64,
+}
+
+// ** every recorded exchange lives in an sqlite file, the frontend only views it
+pub struct HistoryStore {
+    connection: Mutex<Connection>,
+    // * declared after the connection, fields drop in order so the file is closed before it goes
+    discarded: Mutex<Option<DiscardedFile>>,
+}
+
+// ** removes a file once the last handle to its store is gone
+struct DiscardedFile(PathBuf);
+
+impl Drop for DiscardedFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            println!("failed to remove discarded history: {}", e);
+        }
+    }
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self, HistoryError> {
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(HistoryError::OpenError(e.to_string()));
+            }
+        }
+        let connection = match Connection::open(path) {
+            Ok(c) => c,
+            Err(e) => return Err(HistoryError::OpenError(e.to_string())),
+        };
+        if let Err(e) = connection.execute_batch(SCHEMA) {
+            return Err(HistoryError::OpenError(e.to_string()));
+        }
+        if let Err(e) = add_missing_columns(&connection) {
+            return Err(HistoryError::OpenError(e.to_string()));
+        }
+        Ok(HistoryStore {
+            connection: Mutex::new(connection),
+            discarded: Mutex::new(None),
+        })
+    }
+
+    // ** sqlite blocks, so async callers run their statements on the blocking pool, not a worker
+    pub async fn run_blocking<T, F>(self: Arc<Self>, f: F) -> Result<T, HistoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&HistoryStore) -> Result<T, HistoryError> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(move || f(&self)).await {
+            Ok(r) => r,
+            Err(e) => Err(HistoryError::TaskError(e.to_string())),
+        }
+    }
+
+    // ** for a file still written by exchanges in flight, it is removed after the last of them
+    pub fn discard_on_drop(&self, path: &Path) {
+        *self.discarded.lock().unwrap() = Some(DiscardedFile(path.to_path_buf()));
+    }
+
+    // ** a request edited in the pilot replaces the one captured first
+    pub fn record_request(&self, rq: &RecordedRequest) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(
+            "INSERT INTO exchanges (pair_id, method, url, request, client_addr, request_size, started_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(pair_id) DO UPDATE SET
+                method = excluded.method, url = excluded.url,
+                request = excluded.request, request_size = excluded.request_size",
+            params![
+                rq.pair_id,
+                rq.method,
+                rq.url,
+                rq.request,
+                rq.client_addr,
+                rq.request_size as i64,
+                rq.started_at
+            ],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    pub fn record_response(&self, rs: &RecordedResponse) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(
+            "UPDATE exchanges SET status = ?2, response = ?3, upstream_addr = ?4,
+                response_size = ?5, finished_at = ?6, connection_reused = ?7, timings = ?8
+             WHERE pair_id = ?1",
+            params![
+                rs.pair_id,
+                rs.status,
+                rs.response,
+                rs.upstream_addr,
+                rs.response_size as i64,
+                rs.finished_at,
+                rs.connection_reused,
+                rs.timings
+            ],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    // ** exchanges that were never recorded are not added for their error
+    pub fn record_error(&self, pair_id: &str, error: &str) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(
+            "UPDATE exchanges SET error = ?2, finished_at = ?3 WHERE pair_id = ?1",
+            params![pair_id, error, now_millis()],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    pub fn record_frame(&self, frame: &RecordedFrame) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(
+            "INSERT INTO websocket_frames (pair_id, direction, opcode, frame, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                frame.pair_id,
+                frame.direction,
+                frame.opcode,
+                frame.frame,
+                frame.timestamp
+            ],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    // ** in the order they crossed the proxy, both directions interleaved
+    pub fn frames(&self, pair_id: &str) -> Result<Vec<FrameRecord>, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = match connection.prepare(
+            "SELECT id, pair_id, direction, opcode, frame, timestamp FROM websocket_frames
+             WHERE pair_id = ?1 ORDER BY id",
+        ) {
+            Ok(s) => s,
+            Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+        };
+        let rows = match statement.query_map(params![pair_id], |r| {
+            Ok(FrameRecord {
+                id: r.get(0)?,
+                pair_id: r.get(1)?,
+                direction: r.get(2)?,
+                opcode: r.get(3)?,
+                frame: r.get(4)?,
+                timestamp: r.get(5)?,
+            })
+        }) {
+            Ok(r) => r,
+            Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+        };
+        let mut frames = Vec::new();
+        for row in rows {
+            match row {
+                Ok(f) => frames.push(f),
+                Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+            }
+        }
+        Ok(frames)
+    }
+
+    // ** oldest first, so pages stay stable while new traffic arrives
+    pub fn list(&self, offset: u64, limit: u64) -> Result<HistoryPage, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        let total: i64 =
+            match connection.query_row("SELECT COUNT(*) FROM exchanges", [], |r| r.get(0)) {
+                Ok(t) => t,
+                Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+            };
+        let mut statement = match connection.prepare(&format!(
+            "SELECT {} FROM exchanges ORDER BY id LIMIT ?1 OFFSET ?2",
+            SUMMARY_COLUMNS
+        )) {
+            Ok(s) => s,
+            Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+        };
+        let rows = match statement.query_map(params![limit as i64, offset as i64], summary_from_row)
+        {
+            Ok(r) => r,
+            Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+        };
+        let mut items = Vec::new();
+        for row in rows {
+            match row {
+                Ok(s) => items.push(s),
+                Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+            }
+        }
+        Ok(HistoryPage {
+            total: total as u64,
+            items,
+        })
+    }
+
+    pub fn get(&self, id: i64) -> Result<Option<ExchangeDetail>, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        let detail = connection
+            .query_row(
+                &format!(
+                    "SELECT {}, request, response, timings FROM exchanges WHERE id = ?1",
+                    SUMMARY_COLUMNS
+                ),
+                params![id],
+                detail_from_row,
+            )
+            .optional();
+        match detail {
+            Ok(d) => Ok(d),
+            Err(e) => Err(HistoryError::ReadError(e.to_string())),
+        }
+    }
+
+    // ** every exchange with its messages, for exports
+    pub fn all(&self) -> Result<Vec<ExchangeDetail>, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        query_details(&connection, "", [])
+    }
+
+    // ** only the picked exchanges are read, in id order, unknown ids are skipped
+    pub fn selected(&self, ids: &[i64]) -> Result<Vec<ExchangeDetail>, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        let mut details = Vec::new();
+        for chunk in ids.chunks(SELECTED_CHUNK_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            details.extend(query_details(
+                &connection,
+                &format!("WHERE id IN ({})", placeholders),
+                params_from_iter(chunk),
+            )?);
+        }
+        details.sort_by_key(|d| d.summary.id);
+        details.dedup_by_key(|d| d.summary.id);
+        Ok(details)
+    }
+
+    // ** exchanges captured elsewhere, written in one go so a broken file adds nothing
+    pub fn import(
+        &self,
+        exchanges: &[(RecordedRequest, Option<RecordedResponse>)],
+    ) -> Result<usize, HistoryError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = match connection.transaction() {
+            Ok(t) => t,
+            Err(e) => return Err(HistoryError::WriteError(e.to_string())),
+        };
+        for (rq, rs) in exchanges {
+            if let Err(e) = transaction.execute(
+                "INSERT INTO exchanges (pair_id, method, url, request, client_addr, request_size, started_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    rq.pair_id,
+                    rq.method,
+                    rq.url,
+                    rq.request,
+                    rq.client_addr,
+                    rq.request_size as i64,
+                    rq.started_at
+                ],
+            ) {
+                return Err(HistoryError::WriteError(e.to_string()));
+            }
+            let rs = match rs {
+                Some(rs) => rs,
+                None => continue,
+            };
+            if let Err(e) = transaction.execute(
+                "UPDATE exchanges SET status = ?2, response = ?3, upstream_addr = ?4,
+                    response_size = ?5, finished_at = ?6, connection_reused = ?7, timings = ?8
+                 WHERE pair_id = ?1",
+                params![
+                    rs.pair_id,
+                    rs.status,
+                    rs.response,
+                    rs.upstream_addr,
+                    rs.response_size as i64,
+                    rs.finished_at,
+                    rs.connection_reused,
+                    rs.timings
+                ],
+            ) {
+                return Err(HistoryError::WriteError(e.to_string()));
+            }
+        }
+        match transaction.commit() {
+            Ok(_) => Ok(exchanges.len()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    pub fn delete(&self, ids: &[i64]) -> Result<(), HistoryError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = match connection.transaction() {
+            Ok(t) => t,
+            Err(e) => return Err(HistoryError::WriteError(e.to_string())),
+        };
+        for id in ids {
+            if let Err(e) = transaction.execute(
+                "DELETE FROM websocket_frames
+                 WHERE pair_id = (SELECT pair_id FROM exchanges WHERE id = ?1)",
+                params![id],
+            ) {
+                return Err(HistoryError::WriteError(e.to_string()));
+            }
+            if let Err(e) = transaction.execute("DELETE FROM exchanges WHERE id = ?1", params![id])
+            {
+                return Err(HistoryError::WriteError(e.to_string()));
+            }
+        }
+        match transaction.commit() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    // ** the project settings travel in the same file as the traffic
+    pub fn read_settings(&self) -> Result<Option<String>, HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection
+            .query_row("SELECT config FROM settings WHERE id = 1", [], |r| r.get(0))
+            .optional()
+        {
+            Ok(c) => Ok(c),
+            Err(e) => Err(HistoryError::ReadError(e.to_string())),
+        }
+    }
+
+    pub fn write_settings(&self, config: &str) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute(
+            "INSERT INTO settings (id, config) VALUES (1, ?1)
+             ON CONFLICT(id) DO UPDATE SET config = excluded.config",
+            params![config],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    // ** a consistent copy of the whole file, taken while the proxy keeps writing
+    pub fn copy_to(&self, path: &Path) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute("VACUUM INTO ?1", params![path.to_string_lossy()]) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), HistoryError> {
+        let connection = self.connection.lock().unwrap();
+        match connection.execute_batch("DELETE FROM websocket_frames; DELETE FROM exchanges;") {
+            Ok(_) => Ok(()),
+            Err(e) => Err(HistoryError::WriteError(e.to_string())),
+        }
+    }
+}
+
+fn query_details<P: Params>(
+    connection: &Connection,
+    filter: &str,
+    params: P,
+) -> Result<Vec<ExchangeDetail>, HistoryError> {
+    let mut statement = match connection.prepare(&format!(
+        "SELECT {}, request, response, timings FROM exchanges {} ORDER BY id",
+        SUMMARY_COLUMNS, filter
+    )) {
+        Ok(s) => s,
+        Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+    };
+    let rows = match statement.query_map(params, detail_from_row) {
+        Ok(r) => r,
+        Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+    };
+    let mut details = Vec::new();
+    for row in rows {
+        match row {
+            Ok(d) => details.push(d),
+            Err(e) => return Err(HistoryError::ReadError(e.to_string())),
+        }
+    }
+    Ok(details)
+}
+
+fn summary_from_row(r: &Row) -> rusqlite::Result<ExchangeSummary> {
+    Ok(ExchangeSummary {
+        id: r.get(0)?,
+        pair_id: r.get(1)?,
+        method: r.get(2)?,
+        url: r.get(3)?,
+        status: r.get(4)?,
+        error: r.get(5)?,
+        client_addr: r.get(6)?,
+        upstream_addr: r.get(7)?,
+        request_size: r.get::<_, i64>(8)? as u64,
+        response_size: r.get::<_, Option<i64>>(9)?.map(|s| s as u64),
+        started_at: r.get(10)?,
+        finished_at: r.get(11)?,
+        connection_reused: r.get(12)?,
+    })
+}
+
+fn detail_from_row(r: &Row) -> rusqlite::Result<ExchangeDetail> {
+    Ok(ExchangeDetail {
+        summary: summary_from_row(r)?,
+        request: r.get(13)?,
+        response: r.get(14)?,
+        timings: r.get(15)?,
+    })
+}
+
+fn add_missing_columns(connection: &Connection) -> rusqlite::Result<()> {
+    for (table, column, definition) in ADDED_COLUMNS {
+        let mut statement = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+        let names = statement.query_map([], |r| r.get::<_, String>(1))?;
+        let mut exists = false;
+        for name in names {
+            if name? == *column {
+                exists = true;
+            }
+        }
+        if !exists {
+            connection.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn now_millis() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(_) => 0,
+    }
+}
@@ -1,5 +1,18 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
 use crate::http_util::error::HttpUtilError;
 
+// ** how a body travels to and from the frontend, text stays readable and anything else is base64
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    #[default]
+    Utf8,
+    Base64,
+}
+
 pub async fn copy_body(body: hyper::Body) -> Result<(hyper::Body, hyper::Body), HttpUtilError> {
     let r = hyper::body::to_bytes(body).await;
     match r {
@@ -11,3 +24,20 @@ pub async fn copy_body(body: hyper::Body) -> Result<(hyper::Body, hyper::Body),
         Err(e) => Err(HttpUtilError::BodyCopyError(e.to_string())),
     }
 }
+
+pub fn body_to_front(bytes: Bytes) -> (String, BodyEncoding) {
+    match String::from_utf8(Vec::<u8>::from(bytes)) {
+        Ok(s) => (s, BodyEncoding::Utf8),
+        Err(e) => (STANDARD.encode(e.as_bytes()), BodyEncoding::Base64),
+    }
+}
+
+pub fn body_from_front(body: &str, encoding: BodyEncoding) -> Result<Bytes, HttpUtilError> {
+    match encoding {
+        BodyEncoding::Utf8 => Ok(Bytes::from(body.to_string())),
+        BodyEncoding::Base64 => match STANDARD.decode(body) {
+            Ok(b) => Ok(Bytes::from(b)),
+            Err(e) => Err(HttpUtilError::BodyDecodeError(e.to_string())),
+        },
+    }
+}
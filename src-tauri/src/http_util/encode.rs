@@ -1,3 +1,5 @@
+use brotli::CompressorReader;
+use brotli::Decompressor;
 use bytes::Bytes;
 use flate2::read::DeflateDecoder;
 use flate2::read::DeflateEncoder;
@@ -9,14 +11,24 @@ use std::io::prelude::*;
 
 use super::error::HttpUtilError;
 
+const BROTLI_BUFFER_SIZE: usize = 4096;
+// * the top levels cost seconds on a large body for a few percent, every edited body pays that
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
 pub enum SupportedEncoding {
     Gzip,
     Deflate,
+    Brotli,
+    Zstd,
     Identity,
 }
 
 impl SupportedEncoding {
-    pub fn from(header: Option<&HeaderValue>) -> Result<Self, HttpUtilError> {
+    // ** every coding listed in a content-encoding header, in the order they were applied
+    // ** one we cannot undo leaves the whole body as it came, it is shown and forwarded raw
+    pub fn from(header: Option<&HeaderValue>) -> Result<Vec<Self>, HttpUtilError> {
         let r = {
             if let Some(hv) = header {
                 hv.to_str()
@@ -25,40 +37,82 @@ impl SupportedEncoding {
             }
         };
         match r {
-            Ok(v) => match v {
-                "gzip" => Ok(SupportedEncoding::Gzip),
-                "deflate" => Ok(SupportedEncoding::Deflate),
-                "identity" => Ok(SupportedEncoding::Identity),
-                "" => Ok(SupportedEncoding::Identity),
-                _ => {
-                    panic!("encoding check is not working");
-                }
-            },
+            Ok(v) => {
+                let encodings = v
+                    .split(',')
+                    .map(|e| e.trim())
+                    .filter(|e| !e.is_empty())
+                    .map(Self::from_token)
+                    .collect::<Result<Vec<Self>, HttpUtilError>>();
+                Ok(encodings.unwrap_or_default())
+            }
             Err(e) => Err(HttpUtilError::MakeSupportedEncodingError(e.to_string())),
         }
     }
 
+    fn from_token(v: &str) -> Result<Self, HttpUtilError> {
+        match v.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(SupportedEncoding::Gzip),
+            "deflate" => Ok(SupportedEncoding::Deflate),
+            "br" => Ok(SupportedEncoding::Brotli),
+            "zstd" => Ok(SupportedEncoding::Zstd),
+            "identity" => Ok(SupportedEncoding::Identity),
+            _ => Err(HttpUtilError::UnsupportedEncodingError(format!(
+                "content-encoding {} is not supported",
+                v
+            ))),
+        }
+    }
+
+    pub fn token(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+            Self::Identity => "identity",
+        }
+    }
+
     pub fn list() -> Vec<SupportedEncoding> {
         vec![
             SupportedEncoding::Gzip,
             SupportedEncoding::Deflate,
+            SupportedEncoding::Brotli,
+            SupportedEncoding::Zstd,
             SupportedEncoding::Identity,
         ]
     }
 
+    // ** `v` is one accept-encoding entry, parameters such as `;q=0.8` are ignored
     pub fn is_supported(v: &str) -> Result<(), HttpUtilError> {
+        let v = match v.split_once(';') {
+            Some((coding, _)) => coding.trim(),
+            None => v.trim(),
+        };
         match v {
-            "gzip" => Ok(()),
-            "deflate" => Ok(()),
-            "identity" => Ok(()),
             "" => Ok(()),
-            _ => Err(HttpUtilError::UnsupportedEncodingError(format!(
-                "content-encoding {} is not supported",
-                v
-            ))),
+            _ => Self::from_token(v).map(|_| ()),
         }
     }
 
+    // ** undo stacked codings, the last applied is removed first
+    pub fn decode_all(encodings: &[Self], original: Bytes) -> Result<Bytes, HttpUtilError> {
+        let mut b = original;
+        for e in encodings.iter().rev() {
+            b = e.decode(b)?;
+        }
+        Ok(b)
+    }
+
+    pub fn encode_all(encodings: &[Self], decoded: Bytes) -> Result<Bytes, HttpUtilError> {
+        let mut b = decoded;
+        for e in encodings.iter() {
+            b = e.encode(b)?;
+        }
+        Ok(b)
+    }
+
     pub fn decode(&self, original: Bytes) -> Result<Bytes, HttpUtilError> {
         match self {
             Self::Gzip => {
@@ -79,6 +133,19 @@ impl SupportedEncoding {
                     Err(e) => Err(HttpUtilError::DecodeError(e.to_string())),
                 }
             }
+            Self::Brotli => {
+                let ob = original.as_ref();
+                let mut bd = Decompressor::new(ob, BROTLI_BUFFER_SIZE);
+                let mut v = Vec::<u8>::new();
+                match bd.read_to_end(&mut v) {
+                    Ok(_) => Ok(Bytes::from(v)),
+                    Err(e) => Err(HttpUtilError::DecodeError(e.to_string())),
+                }
+            }
+            Self::Zstd => match zstd::stream::decode_all(original.as_ref()) {
+                Ok(v) => Ok(Bytes::from(v)),
+                Err(e) => Err(HttpUtilError::DecodeError(e.to_string())),
+            },
             Self::Identity => Ok(original),
         }
     }
@@ -103,7 +170,25 @@ impl SupportedEncoding {
                     Err(e) => Err(HttpUtilError::EncodeError(e.to_string())),
                 }
             }
+            Self::Brotli => {
+                let eb = encoded_bytes.as_ref();
+                let mut be = CompressorReader::new(
+                    eb,
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_LG_WINDOW_SIZE,
+                );
+                let mut v = Vec::new();
+                match be.read_to_end(&mut v) {
+                    Ok(_) => Ok(Bytes::from(v)),
+                    Err(e) => Err(HttpUtilError::EncodeError(e.to_string())),
+                }
+            }
+            Self::Zstd => match zstd::stream::encode_all(encoded_bytes.as_ref(), ZSTD_LEVEL) {
+                Ok(v) => Ok(Bytes::from(v)),
+                Err(e) => Err(HttpUtilError::EncodeError(e.to_string())),
+            },
             Self::Identity => Ok(encoded_bytes),
         }
     }
-}
\ No newline at end of file
+}
@@ -5,6 +5,8 @@ pub enum HttpUtilError {
     // ** body.rs
     #[error(" >>> failed to copy body >>> `{0}`")]
     BodyCopyError(String),
+    #[error(" >>> failed to decode body from frontend >>> `{0}`")]
+    BodyDecodeError(String),
     // ** encode.rs
     #[error(" >>> failed to make SupportedEncoding onject >>> `{0}`")]
     MakeSupportedEncodingError(String),
@@ -21,6 +23,11 @@ pub enum HttpUtilError {
     HeaderConvertError(String),
     #[error(" >>> invalid http version >>> `{0}`")]
     InvalidHttpVersionError(String),
+    // ** intercept.rs
+    #[error(" >>> failed to wait for intercepted exchange >>> `{0}`")]
+    InterceptWaitError(String),
+    #[error(" >>> no intercepted exchange to resolve >>> `{0}`")]
+    NoPendingExchangeError(String),
     // ** request.rs
     #[error(" >>> failed to create request from hyper::request >>> `{0}`")]
     RequestFromHyperError(String),
@@ -45,6 +52,17 @@ pub enum HttpUtilError {
     ResponseCopyError(String),
     #[error(" >>> failed to decode response >>> `{0}`")]
     ResponseDecodeError(String),
-    #[error(" >>> failed to generate self-certificate >>> `{0}`")]
-    CertificateGenerateError(String)
+    // ** tls.rs
+    #[error(" >>> failed to generate certificate >>> `{0}`")]
+    CertificateGenerateError(String),
+    #[error(" >>> failed to sign certificate >>> `{0}`")]
+    CertificateSignError(String),
+    #[error(" >>> failed to build tls identity >>> `{0}`")]
+    IdentityBuildError(String),
+    #[error(" >>> failed to load certificate authority >>> `{0}`")]
+    CertificateLoadError(String),
+    #[error(" >>> failed to save certificate authority >>> `{0}`")]
+    CertificateSaveError(String),
+    #[error(" >>> failed to export certificate authority >>> `{0}`")]
+    CertificateExportError(String),
 }
@@ -1,48 +1,66 @@
 use async_trait::async_trait;
-use http::header::ACCEPT_ENCODING;
+use http::header::{ACCEPT_ENCODING, CONTENT_LENGTH};
 use hyper::{
     header::{HeaderName, HeaderValue},
     HeaderMap,
 };
-use serde_json::{Map, Value};
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use uuid::Uuid;
 
-use super::{config::PAIR_ID_HEADER_NAME, error::HttpUtilError};
+use super::{
+    body::{body_from_front, body_to_front, BodyEncoding},
+    config::PAIR_ID_HEADER_NAME,
+    encode::SupportedEncoding,
+    error::HttpUtilError,
+};
+
+// ** one header line, repeated names stay separate entries in their original order
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HeaderEntry {
+    pub name: String,
+    pub value: String,
+    #[serde(default)]
+    pub value_encoding: BodyEncoding,
+}
+
+impl HeaderEntry {
+    pub fn new(name: &str, value: &HeaderValue) -> Self {
+        let (value, value_encoding) =
+            body_to_front(bytes::Bytes::copy_from_slice(value.as_bytes()));
+        HeaderEntry {
+            name: name.to_string(),
+            value,
+            value_encoding,
+        }
+    }
+}
 
 #[async_trait]
 impl crate::http_util::traits::HeaderMapMethods for HeaderMap {
     async fn from_json(json_data: String) -> Result<HeaderMap, HttpUtilError> {
-        let json_h = json_data;
-        let r = serde_json::from_str(&json_h);
+        let entries: Vec<HeaderEntry> = match serde_json::from_str(&json_data) {
+            Ok(e) => e,
+            Err(e) => return Err(HttpUtilError::JsonHeadersParseError(e.to_string())),
+        };
 
-        if let Err(e) = r {
-            return Err(HttpUtilError::JsonHeadersParseError(e.to_string()));
-        }
-        let mut hashmap_h: Map<String, Value> = r.unwrap();
-
-        hashmap_h.remove(PAIR_ID_HEADER_NAME);
         let mut h = HeaderMap::new();
-        for (k, v) in hashmap_h {
-            let r = HeaderName::from_str(k.as_str());
-            if let Err(e) = r {
-                return Err(HttpUtilError::JsonHeadersParseError(e.to_string()));
-            }
-            let k = r.unwrap();
-
-            let r = v.as_str();
-            if r.is_none() {
-                return Err(HttpUtilError::JsonHeadersParseError(
-                    "header value is empty".to_string(),
-                ));
-            }
-            let v_str = r.unwrap();
-
-            let r = HeaderValue::from_str(v_str);
-            if let Err(e) = r {
-                return Err(HttpUtilError::JsonHeadersParseError(e.to_string()));
+        for entry in entries {
+            if entry.name.eq_ignore_ascii_case(PAIR_ID_HEADER_NAME) {
+                continue;
             }
-            let v = r.unwrap();
+            let k = match HeaderName::from_str(entry.name.as_str()) {
+                Ok(k) => k,
+                Err(e) => return Err(HttpUtilError::JsonHeadersParseError(e.to_string())),
+            };
+            let v_bytes = match body_from_front(&entry.value, entry.value_encoding) {
+                Ok(b) => b,
+                Err(e) => return Err(HttpUtilError::JsonHeadersParseError(e.to_string())),
+            };
+            let v = match HeaderValue::from_maybe_shared(v_bytes) {
+                Ok(v) => v,
+                Err(e) => return Err(HttpUtilError::JsonHeadersParseError(e.to_string())),
+            };
 
             h.append(k, v);
         }
@@ -50,51 +68,92 @@ impl crate::http_util::traits::HeaderMapMethods for HeaderMap {
     }
 
     async fn json(&self, id: Option<&Uuid>) -> Result<String, HttpUtilError> {
-        let mut header_json_map = Map::<String, Value>::new();
+        let mut entries = Vec::<HeaderEntry>::new();
         if let Some(id) = id {
-            let id_str = id
-                .as_bytes()
-                .iter()
-                .map(|b| format!("{:02X}", b))
-                .collect::<Vec<String>>()
-                .join("");
-            header_json_map.insert(PAIR_ID_HEADER_NAME.to_string(), Value::from(id_str));
+            let id_str = pair_id_string(id);
+            entries.push(HeaderEntry {
+                name: PAIR_ID_HEADER_NAME.to_string(),
+                value: id_str,
+                value_encoding: BodyEncoding::Utf8,
+            });
         }
         for (name, value) in self {
-            let name = name.to_string();
-
-            match value.to_str() {
-                Ok(v_str) => {
-                    let value = Value::from(v_str);
-                    let _ = header_json_map.insert(name, value);
-                }
-                Err(e) => {
-                    return Err(HttpUtilError::HeaderConvertError(e.to_string()));
-                }
-            }
+            entries.push(HeaderEntry::new(name.as_str(), value));
         }
 
-        match serde_json::to_string(&header_json_map) {
+        match serde_json::to_string(&entries) {
             Ok(h) => Ok(h),
             Err(e) => Err(HttpUtilError::HeaderConvertError(e.to_string())),
         }
     }
 
-    fn check_encoding(&self) -> Result<(), HttpUtilError> {
-        match self.get(ACCEPT_ENCODING) {
-            Some(ae) => {
-                let ae_list: Vec<&str> = ae.to_str().unwrap().split(',').collect();
-                for e_ae in ae_list {
-                    let e_ae = e_ae.replace(' ', "");
-                    crate::http_util::encode::SupportedEncoding::is_supported(e_ae.as_str())?;
+    // ** codings the proxy could not decode are left out, so the upstream never picks one of them
+    fn retain_supported_encodings(&mut self) {
+        if !self.contains_key(ACCEPT_ENCODING) {
+            return;
+        }
+        // * a value that is not text cannot name anything we decode
+        let entries: Vec<String> = self
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|ae| ae.to_str().ok())
+            .flat_map(|ae| ae.split(','))
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty())
+            .collect();
+        let coding = |e: &str| match e.split_once(';') {
+            Some((c, _)) => c.trim().to_ascii_lowercase(),
+            None => e.to_ascii_lowercase(),
+        };
+        let mut supported = Vec::new();
+        for e_ae in &entries {
+            if coding(e_ae) != "*" {
+                if SupportedEncoding::is_supported(e_ae).is_ok() {
+                    supported.push(e_ae.clone());
+                }
+                continue;
+            }
+            // * the wildcard would let the upstream pick any coding, it stands for ours instead
+            // * with its weight, the ones listed by name keep their own
+            let weight = e_ae.split_once(';').map(|(_, w)| w.trim());
+            for e in SupportedEncoding::list() {
+                if entries.iter().any(|listed| coding(listed) == e.token()) {
+                    continue;
                 }
-                Ok(())
+                supported.push(match weight {
+                    Some(w) => format!("{};{}", e.token(), w),
+                    None => e.token().to_string(),
+                });
             }
-            None => Ok(()),
         }
+        // * nothing left, an identity-only answer is still one we can read
+        let ae = match supported.is_empty() {
+            true => "identity".to_string(),
+            false => supported.join(", "),
+        };
+        match HeaderValue::from_str(&ae) {
+            Ok(v) => self.insert(ACCEPT_ENCODING, v),
+            Err(_) => self.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity")),
+        };
+    }
+}
+
+// ** keep a declared content-length in line with a body that was edited or re-encoded
+pub fn fix_content_length(headers: &mut HeaderMap, len: usize) {
+    if headers.contains_key(CONTENT_LENGTH) {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
     }
 }
 
+// ** the pair id as the frontend sees it in the `pair-id` header
+pub fn pair_id_string(id: &Uuid) -> String {
+    id.as_bytes()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
 impl crate::http_util::traits::VersionMethods for hyper::Version {
     fn to_string(&self) -> Result<String, HttpUtilError> {
         match *self {
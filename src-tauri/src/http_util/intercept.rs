@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::{sync::Mutex, time::Duration};
+use tauri::{AppHandle, Manager};
+use tokio::sync::oneshot;
+
+use super::config::PAIR_ID_HEADER_NAME;
+use super::error::HttpUtilError;
+use super::header::HeaderEntry;
+
+const INTERCEPT_QUEUE_EVENT: &str = "intercept-queue";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InterceptKind {
+    Request,
+    Response,
+    // * websocket messages, payload is the FrameForFront json
+    ClientMessage,
+    ServerMessage,
+}
+
+// ** what to do with a held message, `payload` is the edited RequestForFront / ResponseForFront json
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum InterceptAction {
+    Forward,
+    ForwardModified { payload: String },
+    Drop { status: u16 },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct InterceptDecision {
+    #[serde(flatten)]
+    pub action: InterceptAction,
+    // * only meaningful for a request, holds its response as well
+    #[serde(default)]
+    pub intercept_response: bool,
+}
+
+impl InterceptDecision {
+    pub fn forward() -> Self {
+        InterceptDecision {
+            action: InterceptAction::Forward,
+            intercept_response: false,
+        }
+    }
+}
+
+// ** what the frontend sees of a held exchange, payload is the RequestForFront / ResponseForFront json
+#[derive(Serialize, Clone)]
+pub struct PendingExchange {
+    pub pair_id: String,
+    pub kind: InterceptKind,
+    pub payload: String,
+}
+
+struct Waiter {
+    exchange: PendingExchange,
+    sender: oneshot::Sender<InterceptDecision>,
+}
+
+// ** every held exchange waits here under its pair id, so a decision from the ui reaches only its own exchange
+pub struct InterceptBroker {
+    queue: Mutex<Vec<Waiter>>,
+}
+
+impl InterceptBroker {
+    pub fn new() -> Self {
+        InterceptBroker {
+            queue: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub async fn wait(
+        &self,
+        app_handle: &AppHandle,
+        pair_id: &str,
+        kind: InterceptKind,
+        payload: String,
+        timeout: Option<Duration>,
+    ) -> Result<InterceptDecision, HttpUtilError> {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue
+                .iter()
+                .any(|w| w.exchange.pair_id == pair_id && w.exchange.kind == kind)
+            {
+                return Err(HttpUtilError::InterceptWaitError(format!(
+                    "{} is already waiting",
+                    pair_id
+                )));
+            }
+            queue.push(Waiter {
+                exchange: PendingExchange {
+                    pair_id: pair_id.to_string(),
+                    kind,
+                    payload,
+                },
+                sender,
+            });
+        }
+        self.notify(app_handle);
+
+        // * the client can go away while we wait, the entry must not outlive this future
+        let _guard = WaiterGuard {
+            broker: self,
+            app_handle,
+            pair_id,
+            kind,
+        };
+        let decision = match timeout {
+            Some(t) => match tokio::time::timeout(t, receiver).await {
+                Ok(d) => d,
+                // * a forgotten breakpoint must not stall the client forever
+                Err(_) => return Ok(InterceptDecision::forward()),
+            },
+            None => receiver.await,
+        };
+        match decision {
+            Ok(d) => Ok(d),
+            Err(e) => Err(HttpUtilError::InterceptWaitError(e.to_string())),
+        }
+    }
+
+    pub fn resolve(
+        &self,
+        app_handle: &AppHandle,
+        pair_id: &str,
+        kind: InterceptKind,
+        decision: InterceptDecision,
+    ) -> Result<(), HttpUtilError> {
+        let waiter = match self.remove(pair_id, kind) {
+            Some(w) => w,
+            None => {
+                return Err(HttpUtilError::NoPendingExchangeError(format!(
+                    "no {:?} is waiting for {}",
+                    kind, pair_id
+                )))
+            }
+        };
+        self.notify(app_handle);
+        match waiter.sender.send(decision) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(HttpUtilError::NoPendingExchangeError(format!(
+                "{} is no longer waiting",
+                pair_id
+            ))),
+        }
+    }
+
+    // ** routes a RequestForFront / ResponseForFront sent back over an event by its `pair-id` header,
+    // ** this older protocol always forwards the payload and leaves the response to the intercept rules
+    pub fn resolve_payload(
+        &self,
+        app_handle: &AppHandle,
+        kind: InterceptKind,
+        payload: &str,
+    ) -> Result<(), HttpUtilError> {
+        let pair_id = payload_pair_id(payload)?;
+        let decision = InterceptDecision {
+            action: InterceptAction::ForwardModified {
+                payload: payload.to_string(),
+            },
+            intercept_response: false,
+        };
+        self.resolve(app_handle, &pair_id, kind, decision)
+    }
+
+    pub fn pending(&self) -> Vec<PendingExchange> {
+        let queue = self.queue.lock().unwrap();
+        queue.iter().map(|w| w.exchange.clone()).collect()
+    }
+
+    // ** listed pair ids move to the front in the given order, the rest keep their relative order
+    pub fn reorder(&self, app_handle: &AppHandle, pair_ids: &[String]) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let mut rest = std::mem::take(&mut *queue);
+            for pair_id in pair_ids {
+                while let Some(i) = rest.iter().position(|w| &w.exchange.pair_id == pair_id) {
+                    queue.push(rest.remove(i));
+                }
+            }
+            queue.append(&mut rest);
+        }
+        self.notify(app_handle);
+    }
+
+    fn remove(&self, pair_id: &str, kind: InterceptKind) -> Option<Waiter> {
+        let mut queue = self.queue.lock().unwrap();
+        let i = queue
+            .iter()
+            .position(|w| w.exchange.pair_id == pair_id && w.exchange.kind == kind)?;
+        Some(queue.remove(i))
+    }
+
+    fn notify(&self, app_handle: &AppHandle) {
+        let pending = match serde_json::to_string(&self.pending()) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("failed to serialize intercept queue: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = app_handle.emit_all(INTERCEPT_QUEUE_EVENT, pending) {
+            println!("failed to emit intercept queue: {}", e);
+        }
+    }
+}
+
+struct WaiterGuard<'a> {
+    broker: &'a InterceptBroker,
+    app_handle: &'a AppHandle,
+    pair_id: &'a str,
+    kind: InterceptKind,
+}
+
+impl Drop for WaiterGuard<'_> {
+    fn drop(&mut self) {
+        if self.broker.remove(self.pair_id, self.kind).is_some() {
+            self.broker.notify(self.app_handle);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PayloadHeaders {
+    headers: String,
+}
+
+fn payload_pair_id(payload: &str) -> Result<String, HttpUtilError> {
+    let p: PayloadHeaders = match serde_json::from_str(payload) {
+        Ok(p) => p,
+        Err(e) => return Err(HttpUtilError::NoPendingExchangeError(e.to_string())),
+    };
+    let entries: Vec<HeaderEntry> = match serde_json::from_str(&p.headers) {
+        Ok(e) => e,
+        Err(e) => return Err(HttpUtilError::NoPendingExchangeError(e.to_string())),
+    };
+    match entries
+        .into_iter()
+        .find(|e| e.name.eq_ignore_ascii_case(PAIR_ID_HEADER_NAME))
+    {
+        Some(e) => Ok(e.value),
+        None => Err(HttpUtilError::NoPendingExchangeError(
+            "payload has no pair-id header".to_string(),
+        )),
+    }
+}
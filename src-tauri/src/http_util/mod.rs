@@ -2,8 +2,10 @@ pub mod body;
 pub mod encode;
 pub mod error;
 pub mod header;
+pub mod intercept;
 pub mod request;
 pub mod response;
+pub mod stream;
 pub mod traits;
 pub mod tls;
-mod config;
+pub mod config;
@@ -1,34 +1,30 @@
 use hyper::{Body, HeaderMap, Method, Uri, Version};
 use serde::{Deserialize, Serialize};
-use std::str::{self};
+use std::{
+    str::{self},
+    time::Duration,
+};
 use tauri::{AppHandle, Manager};
-use tokio::sync::mpsc;
 
-use super::body::copy_body;
+use super::body::{body_from_front, body_to_front, copy_body, BodyEncoding};
 use super::error::HttpUtilError;
+use super::header::{fix_content_length, pair_id_string};
+use super::intercept::{InterceptBroker, InterceptDecision, InterceptKind};
 use super::traits::HeaderMapMethods;
 use super::traits::VersionMethods;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct RequestForFront {
     pub headers: String,
     pub url: String,
     pub method: String,
     pub version: String,
     pub body: String,
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
 }
 
 impl RequestForFront {
-    pub fn new() -> Self {
-        RequestForFront {
-            headers: "".to_string(),
-            url: "".to_string(),
-            method: "".to_string(),
-            version: "".to_string(),
-            body: "".to_string(),
-        }
-    }
-
     pub async fn from_hyper(
         request: hyper::Request<hyper::Body>,
         pair_id: Option<&uuid::Uuid>,
@@ -54,11 +50,7 @@ impl RequestForFront {
             Ok(b) => b,
             Err(e) => return Err(HttpUtilError::RequestFromHyperError(e.to_string())),
         };
-        let body_vec = Vec::<u8>::from(body_bytes);
-        let body = match String::from_utf8(body_vec) {
-            Ok(b) => b,
-            Err(e) => return Err(HttpUtilError::RequestFromHyperError(e.to_string())),
-        };
+        let (body, body_encoding) = body_to_front(body_bytes);
 
         Ok(RequestForFront {
             headers,
@@ -66,11 +58,12 @@ impl RequestForFront {
             method,
             version,
             body,
+            body_encoding,
         })
     }
 
     pub async fn to_hyper(self) -> Result<hyper::Request<hyper::Body>, HttpUtilError> {
-        let headers = match HeaderMap::from_json(self.headers).await {
+        let mut headers = match HeaderMap::from_json(self.headers).await {
             Ok(h) => h,
             Err(e) => return Err(HttpUtilError::RequestToHyperError(e.to_string())),
         };
@@ -86,19 +79,22 @@ impl RequestForFront {
             Ok(v) => v,
             Err(e) => return Err(HttpUtilError::RequestToHyperError(e.to_string())),
         };
-        let body = Body::from(self.body);
+        let body = match body_from_front(&self.body, self.body_encoding) {
+            Ok(b) => b,
+            Err(e) => return Err(HttpUtilError::RequestToHyperError(e.to_string())),
+        };
+        fix_content_length(&mut headers, body.len());
+        let body = Body::from(body);
 
         let mut request = hyper::Request::builder();
-        for (name, value) in headers {
-            request = request.header(name.unwrap(), value);
+        for (name, value) in &headers {
+            request = request.header(name, value);
         }
 
-        Ok(request
-            .uri(uri)
-            .method(method)
-            .version(version)
-            .body(body)
-            .unwrap())
+        match request.uri(uri).method(method).version(version).body(body) {
+            Ok(rq) => Ok(rq),
+            Err(e) => Err(HttpUtilError::RequestToHyperError(e.to_string())),
+        }
     }
 
     pub async fn send_to_front(&self, app_handle: &AppHandle) -> Result<(), HttpUtilError> {
@@ -112,53 +108,42 @@ impl RequestForFront {
         }
     }
 
-    pub async fn wait_for_modification(
+    pub async fn wait_for_decision(
         &self,
+        broker: &InterceptBroker,
         app_handle: &AppHandle,
-    ) -> Result<Self, HttpUtilError> {
-        let (sender, mut reciever) = mpsc::channel(400);
-
-        app_handle.listen_global("pilot-send-request", move |event| {
-            let sender = sender.clone();
-            tokio::spawn(async move {
-                let rq_str = match event.payload() {
-                    Some(r) => r,
-                    None => {
-                        sender
-                            .send(Err(HttpUtilError::ModifiedRequestReceiveError(
-                                "received payload is empty".to_string(),
-                            )))
-                            .await
-                            .unwrap();
-                        panic!();
-                    }
-                };
-                match serde_json::from_str(rq_str) {
-                    Ok(rq) => {
-                        sender.send(Ok(rq)).await.unwrap();
-                    }
-                    Err(e) => {
-                        sender
-                            .send(Err(HttpUtilError::ModifiedRequestReceiveError(
-                                e.to_string(),
-                            )))
-                            .await
-                            .unwrap();
-                    }
-                };
-            });
-        });
-
-        match reciever.recv().await {
-            Some(r) => match r {
-                Ok(rq) => {
-                    let mut rq: RequestForFront= rq;
-                    rq.url = self.url.clone();
-                    Ok(rq)
-                }
-                Err(e) => Err(e),
-            },
-            None => Ok(RequestForFront::new()),
+        pair_id: &uuid::Uuid,
+        timeout: Option<Duration>,
+    ) -> Result<InterceptDecision, HttpUtilError> {
+        let payload = match serde_json::to_string(self) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::ModifiedRequestReceiveError(e.to_string())),
+        };
+        let pair_id = pair_id_string(pair_id);
+        match broker
+            .wait(
+                app_handle,
+                &pair_id,
+                InterceptKind::Request,
+                payload,
+                timeout,
+            )
+            .await
+        {
+            Ok(d) => Ok(d),
+            Err(e) => Err(HttpUtilError::ModifiedRequestReceiveError(e.to_string())),
+        }
+    }
+
+    // ** the edited rq carried by a forward_modified decision
+    pub fn modified(&self, payload: &str) -> Result<Self, HttpUtilError> {
+        match serde_json::from_str::<RequestForFront>(payload) {
+            Ok(mut rq) => {
+                // * the target can not be changed from the pilot
+                rq.url = self.url.clone();
+                Ok(rq)
+            }
+            Err(e) => Err(HttpUtilError::ModifiedRequestReceiveError(e.to_string())),
         }
     }
 }
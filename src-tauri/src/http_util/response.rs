@@ -1,40 +1,35 @@
 use bytes::Bytes;
 use http::header::CONTENT_ENCODING;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tauri::AppHandle;
 use tauri::Manager;
-use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use super::body::copy_body;
+use super::body::{body_from_front, body_to_front, copy_body, BodyEncoding};
 use super::encode::SupportedEncoding;
 use super::error::HttpUtilError;
+use super::header::{fix_content_length, pair_id_string};
+use super::intercept::{InterceptBroker, InterceptDecision, InterceptKind};
 use super::traits::HeaderMapMethods;
 use super::traits::VersionMethods;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct ResponseForFront {
     pub headers: String,
     pub body: String,
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
     pub status: u16,
     pub version: String,
 }
 
 impl ResponseForFront {
-    pub fn new() -> Self {
-        ResponseForFront {
-            headers: "".to_string(),
-            body: "".to_string(),
-            version: "".to_string(),
-            status: 500,
-        }
-    }
-
     pub async fn from_hyper(
         response: hyper::Response<hyper::Body>,
         pair_id: Option<&Uuid>,
     ) -> Result<Self, HttpUtilError> {
-        let (p, s) = match decode_response(response).await {
+        let (p, b) = match decode_response(response).await {
             Ok(t) => t,
             Err(e) => return Err(HttpUtilError::ResponseFromHyperError(e.to_string())),
         };
@@ -46,16 +41,18 @@ impl ResponseForFront {
             Ok(v) => v,
             Err(e) => return Err(HttpUtilError::ResponseFromHyperError(e.to_string())),
         };
+        let (body, body_encoding) = body_to_front(b);
         Ok(Self {
             headers: h,
             version: v,
             status: p.status.as_u16(),
-            body: s,
+            body,
+            body_encoding,
         })
     }
 
     pub async fn to_hyper(&self) -> Result<hyper::Response<hyper::Body>, HttpUtilError> {
-        let h = match hyper::HeaderMap::from_json(self.headers.clone()).await {
+        let mut h = match hyper::HeaderMap::from_json(self.headers.clone()).await {
             Ok(h) => h,
             Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
         };
@@ -63,22 +60,29 @@ impl ResponseForFront {
             Ok(v) => v,
             Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
         };
-        let s = hyper::StatusCode::from_u16(self.status).unwrap();
+        let s = match hyper::StatusCode::from_u16(self.status) {
+            Ok(s) => s,
+            Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
+        };
 
-        let b_bytes = Bytes::from(self.body.clone());
-        let s_encoding =
-            crate::http_util::encode::SupportedEncoding::from(h.get(CONTENT_ENCODING)).unwrap();
-        let e_bytes = match s_encoding.encode(b_bytes) {
+        let b_bytes = match body_from_front(&self.body, self.body_encoding) {
+            Ok(b) => b,
+            Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
+        };
+        let s_encodings = match SupportedEncoding::from(h.get(CONTENT_ENCODING)) {
+            Ok(s) => s,
+            Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
+        };
+        let e_bytes = match SupportedEncoding::encode_all(&s_encodings, b_bytes) {
             Ok(b) => b,
             Err(e) => return Err(HttpUtilError::ResponseToHyperError(e.to_string())),
         };
+        fix_content_length(&mut h, e_bytes.len());
         let b = hyper::Body::from(e_bytes);
 
         let mut rs = hyper::Response::builder().version(v).status(s);
-        for (k, v) in h {
-            if let Some(k) = k {
-                rs = rs.header(k, v);
-            }
+        for (k, v) in &h {
+            rs = rs.header(k, v);
         }
         match rs.body(b) {
             Ok(rs) => Ok(rs),
@@ -97,44 +101,38 @@ impl ResponseForFront {
         }
     }
 
-    pub async fn wait_for_modification(
+    pub async fn wait_for_decision(
         &self,
+        broker: &InterceptBroker,
         app_handle: &AppHandle,
-    ) -> Result<Self, HttpUtilError> {
-        let (sender, mut reciever) = mpsc::channel(400);
-
-        app_handle.listen_global("pilot-send-response", move |event| {
-            let sender = sender.clone();
-            tokio::spawn(async move {
-                let rs_str = match event.payload() {
-                    Some(s) => s,
-                    None => {
-                        sender
-                            .send(Err(HttpUtilError::ModifiedResponseReceiveError(
-                                "received payload is empty".to_string(),
-                            )))
-                            .await
-                            .unwrap();
-                        panic!();
-                    }
-                };
-                match serde_json::from_str(rs_str) {
-                    Ok(rs) => sender.send(Ok(rs)).await.unwrap(),
-                    Err(e) => {
-                        sender
-                            .send(Err(HttpUtilError::ModifiedResponseReceiveError(
-                                e.to_string(),
-                            )))
-                            .await
-                            .unwrap();
-                    }
-                };
-            });
-        });
-
-        match reciever.recv().await {
-            Some(rs) => rs,
-            None => Ok(ResponseForFront::new()),
+        pair_id: &Uuid,
+        timeout: Option<Duration>,
+    ) -> Result<InterceptDecision, HttpUtilError> {
+        let payload = match serde_json::to_string(self) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::ModifiedResponseReceiveError(e.to_string())),
+        };
+        let pair_id = pair_id_string(pair_id);
+        match broker
+            .wait(
+                app_handle,
+                &pair_id,
+                InterceptKind::Response,
+                payload,
+                timeout,
+            )
+            .await
+        {
+            Ok(d) => Ok(d),
+            Err(e) => Err(HttpUtilError::ModifiedResponseReceiveError(e.to_string())),
+        }
+    }
+
+    // ** the edited rs carried by a forward_modified decision
+    pub fn modified(&self, payload: &str) -> Result<Self, HttpUtilError> {
+        match serde_json::from_str::<ResponseForFront>(payload) {
+            Ok(rs) => Ok(rs),
+            Err(e) => Err(HttpUtilError::ModifiedResponseReceiveError(e.to_string())),
         }
     }
 }
@@ -180,21 +178,20 @@ pub async fn copy_response(
 
 async fn decode_response(
     response: hyper::Response<hyper::Body>,
-) -> Result<(http::response::Parts, String), HttpUtilError> {
+) -> Result<(http::response::Parts, Bytes), HttpUtilError> {
     let (parts, body) = response.into_parts();
     let ce = parts.headers.get(CONTENT_ENCODING);
     let se = match SupportedEncoding::from(ce) {
         Ok(s) => s,
         Err(e) => return Err(HttpUtilError::ResponseDecodeError(e.to_string())),
     };
-    let b = match se.decode(hyper::body::to_bytes(body).await.unwrap()) {
+    let b = match hyper::body::to_bytes(body).await {
         Ok(b) => b,
         Err(e) => return Err(HttpUtilError::ResponseDecodeError(e.to_string())),
     };
-    let v = Vec::<u8>::from(b);
-    let s = match String::from_utf8(v) {
-        Ok(s) => s,
+    let b = match SupportedEncoding::decode_all(&se, b) {
+        Ok(b) => b,
         Err(e) => return Err(HttpUtilError::ResponseDecodeError(e.to_string())),
     };
-    Ok((parts, s))
+    Ok((parts, b))
 }
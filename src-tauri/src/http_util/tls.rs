@@ -1,13 +1,558 @@
-use rcgen::generate_simple_self_signed;
+use lru::LruCache;
+use native_tls::Identity;
+use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use serde::Deserialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use time::{Duration, OffsetDateTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use super::error::HttpUtilError;
 
-pub async fn generate_certificate() -> Result<(), HttpUtilError> {
-    let subject_alt_names = vec!["localhost:8080".to_string(), "127.0.0.1:8080".to_string()];
-    let cert = generate_simple_self_signed(subject_alt_names).unwrap();
-    println!("{}", cert.serialize_pem().unwrap());
-    println!("{}", cert.serialize_private_key_pem());
-    Ok(())
+const CA_COMMON_NAME: &str = "rsproxy CA";
+const CA_ORGANIZATION_NAME: &str = "rsproxy";
+const CA_CERT_FILE_NAME: &str = "ca.pem";
+const CA_KEY_FILE_NAME: &str = "ca.key";
+const CA_VALIDITY_DAYS: i64 = 3650;
+const LEAF_VALIDITY_DAYS: i64 = 365;
+const LEAF_RENEW_MARGIN_DAYS: i64 = 1;
+pub const LEAF_CACHE_CAPACITY: usize = 1024;
+const LEAF_CERT_EXTENSION: &str = "pem";
+const LEAF_KEY_EXTENSION: &str = "key";
+// * `a.b.example.com` and longer share a wildcard leaf
+const WILDCARD_MIN_LABELS: usize = 4;
+
+const TLS_RECORD_HEADER_LEN: usize = 5;
+const TLS_MAX_RECORD_LEN: usize = 16384;
+pub const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 0x01;
+const TLS_EXTENSION_SERVER_NAME: u16 = 0x0000;
+const TLS_SERVER_NAME_HOST: u8 = 0x00;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CertificateFormat {
+    Pem,
+    Der,
+    Pkcs12,
+}
+
+pub struct CertificateAuthority {
+    cert: Certificate,
+    cert_pem: String,
+    cert_der: Vec<u8>,
+    dir: PathBuf,
+    cache: Mutex<CertificateCache>,
+}
+
+struct CachedCertificate {
+    identity: Identity,
+    not_after: OffsetDateTime,
+}
+
+// ** signed leaf certificates keyed by host name, `*.parent` for wildcard entries or the ip address
+pub struct CertificateCache {
+    entries: LruCache<String, CachedCertificate>,
+    dir: Option<PathBuf>,
+}
+
+impl CertificateCache {
+    pub fn new(capacity: usize, dir: Option<PathBuf>) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        if let Some(dir) = &dir {
+            prune(dir, capacity.get());
+        }
+        CertificateCache {
+            entries: LruCache::new(capacity),
+            dir,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Identity> {
+        let fresh = match self.entries.get(key) {
+            Some(c) => is_fresh(c.not_after),
+            None => return None,
+        };
+        if !fresh {
+            self.entries.pop(key);
+            return None;
+        }
+        self.entries.get(key).map(|c| c.identity.clone())
+    }
+
+    fn put(&mut self, key: String, cert: CachedCertificate) {
+        self.entries.put(key, cert);
+    }
+
+    // ** read a leaf persisted by a previous run, expired ones are ignored
+    fn load(&self, key: &str) -> Option<CachedCertificate> {
+        let (cert_path, key_path) = self.paths(key)?;
+        read_leaf(&cert_path, &key_path)
+    }
+
+    fn store(&self, key: &str, cert_pem: &str, key_pem: &str) -> Result<(), HttpUtilError> {
+        let (cert_path, key_path) = match self.paths(key) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        if let Some(dir) = &self.dir {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+            }
+        }
+        if let Err(e) = fs::write(cert_path, cert_pem) {
+            return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+        }
+        if let Err(e) = write_private(&key_path, key_pem) {
+            return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+
+    fn paths(&self, key: &str) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_ref()?;
+        let name = key.replace('*', "_wildcard").replace(':', "_");
+        Some((
+            dir.join(format!("{}.{}", name, LEAF_CERT_EXTENSION)),
+            dir.join(format!("{}.{}", name, LEAF_KEY_EXTENSION)),
+        ))
+    }
 }
 
-// openssl req -newkey rsa:2048 -nodes -keyout key.pem -x509 -days 365 -out cert.pem
+impl CertificateAuthority {
+    // ** reuse the ca stored in `dir`, or create and store a new one
+    pub fn load_or_generate(dir: &Path) -> Result<Self, HttpUtilError> {
+        let cert_path = dir.join(CA_CERT_FILE_NAME);
+        let key_path = dir.join(CA_KEY_FILE_NAME);
+        if cert_path.exists() && key_path.exists() {
+            return Self::load(dir);
+        }
+        let ca = Self::generate(dir)?;
+        ca.save()?;
+        Ok(ca)
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, HttpUtilError> {
+        let cert_pem = match fs::read_to_string(dir.join(CA_CERT_FILE_NAME)) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+        let key_pem = match fs::read_to_string(dir.join(CA_KEY_FILE_NAME)) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+        let key_pair = match KeyPair::from_pem(&key_pem) {
+            Ok(k) => k,
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+        let params = match CertificateParams::from_ca_cert_pem(&cert_pem, key_pair) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+        let cert = match Certificate::from_params(params) {
+            Ok(c) => c,
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+        let cert_der = match pem::parse(&cert_pem) {
+            Ok(p) => p.into_contents(),
+            Err(e) => return Err(HttpUtilError::CertificateLoadError(e.to_string())),
+        };
+
+        Ok(CertificateAuthority {
+            cert,
+            cert_pem,
+            cert_der,
+            dir: dir.to_path_buf(),
+            cache: Mutex::new(CertificateCache::new(LEAF_CACHE_CAPACITY, None)),
+        })
+    }
+
+    pub fn generate(dir: &Path) -> Result<Self, HttpUtilError> {
+        let mut params = CertificateParams::default();
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CommonName, CA_COMMON_NAME);
+        dn.push(DnType::OrganizationName, CA_ORGANIZATION_NAME);
+        params.distinguished_name = dn;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(CA_VALIDITY_DAYS);
+
+        let cert = match Certificate::from_params(params) {
+            Ok(c) => c,
+            Err(e) => return Err(HttpUtilError::CertificateGenerateError(e.to_string())),
+        };
+        // ** serialize once, every serialization is signed again and gives different bytes
+        let cert_der = match cert.serialize_der() {
+            Ok(d) => d,
+            Err(e) => return Err(HttpUtilError::CertificateGenerateError(e.to_string())),
+        };
+        let cert_pem = pem::encode(&pem::Pem::new("CERTIFICATE", cert_der.clone()));
+
+        Ok(CertificateAuthority {
+            cert,
+            cert_pem,
+            cert_der,
+            dir: dir.to_path_buf(),
+            cache: Mutex::new(CertificateCache::new(LEAF_CACHE_CAPACITY, None)),
+        })
+    }
+
+    pub fn with_cache(mut self, cache: CertificateCache) -> Self {
+        self.cache = Mutex::new(cache);
+        self
+    }
+
+    pub fn save(&self) -> Result<(), HttpUtilError> {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+        }
+        if let Err(e) = fs::write(self.dir.join(CA_CERT_FILE_NAME), &self.cert_pem) {
+            return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+        }
+        if let Err(e) = write_private(
+            &self.dir.join(CA_KEY_FILE_NAME),
+            &self.cert.serialize_private_key_pem(),
+        ) {
+            return Err(HttpUtilError::CertificateSaveError(e.to_string()));
+        }
+        Ok(())
+    }
+
+    // ** replace the stored ca with a fresh one, certificates already installed on clients stop working
+    pub fn rotate(&mut self) -> Result<(), HttpUtilError> {
+        let ca = Self::generate(&self.dir)?;
+        ca.save()?;
+        self.cert = ca.cert;
+        self.cert_pem = ca.cert_pem;
+        self.cert_der = ca.cert_der;
+        // ** leaves signed by the old ca are useless now
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    pub fn export(
+        &self,
+        format: CertificateFormat,
+        password: Option<&str>,
+    ) -> Result<Vec<u8>, HttpUtilError> {
+        match format {
+            CertificateFormat::Pem => Ok(self.cert_pem.clone().into_bytes()),
+            CertificateFormat::Der => Ok(self.cert_der.clone()),
+            CertificateFormat::Pkcs12 => self.export_pkcs12(password.unwrap_or("")),
+        }
+    }
+
+    fn export_pkcs12(&self, password: &str) -> Result<Vec<u8>, HttpUtilError> {
+        let cert = match p12_keystore::Certificate::from_der(&self.cert_der) {
+            Ok(c) => c,
+            Err(e) => return Err(HttpUtilError::CertificateExportError(e.to_string())),
+        };
+        let key_chain = PrivateKeyChain::new(
+            self.cert.serialize_private_key_der(),
+            self.cert.get_key_identifier(),
+            vec![cert],
+        );
+        let mut key_store = KeyStore::new();
+        key_store.add_entry(CA_COMMON_NAME, KeyStoreEntry::PrivateKeyChain(key_chain));
+        match key_store.writer(password).write() {
+            Ok(b) => Ok(b),
+            Err(e) => Err(HttpUtilError::CertificateExportError(e.to_string())),
+        }
+    }
+
+    // ** leaf certificate for `host`, signed once and then served from the cache
+    pub fn certificate_for(&self, host: &str) -> Result<Identity, HttpUtilError> {
+        // * `[::1]` as it appears in an authority
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let key = cache_key(host);
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(identity) = cache.get(&key) {
+                return Ok(identity);
+            }
+            if let Some(cert) = cache.load(&key) {
+                let identity = cert.identity.clone();
+                cache.put(key, cert);
+                return Ok(identity);
+            }
+        }
+
+        // * signed without the lock, two handshakes racing for one host just sign twice
+        let mut names = vec![host.to_string()];
+        if key != host {
+            names.push(key.clone());
+        }
+        let (cert_pem, key_pem, not_after) = self.generate_certificate(names)?;
+        let identity = match Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes()) {
+            Ok(i) => i,
+            Err(e) => return Err(HttpUtilError::IdentityBuildError(e.to_string())),
+        };
+        let mut cache = self.cache.lock().unwrap();
+        if let Err(e) = cache.store(&key, &cert_pem, &key_pem) {
+            println!("certificate cache error{}", e);
+        }
+        cache.put(
+            key,
+            CachedCertificate {
+                identity: identity.clone(),
+                not_after,
+            },
+        );
+        Ok(identity)
+    }
+
+    // ** issue a leaf certificate for `names` signed by this authority, returns (cert pem, key pem, expiry)
+    fn generate_certificate(
+        &self,
+        names: Vec<String>,
+    ) -> Result<(String, String, OffsetDateTime), HttpUtilError> {
+        let common_name = names[0].clone();
+        let mut params = CertificateParams::new(names);
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CommonName, common_name);
+        dn.push(DnType::OrganizationName, CA_ORGANIZATION_NAME);
+        params.distinguished_name = dn;
+        params.serial_number = Some(SerialNumber::from_slice(uuid::Uuid::new_v4().as_bytes()));
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let now = OffsetDateTime::now_utc();
+        let not_after = now + Duration::days(LEAF_VALIDITY_DAYS);
+        params.not_before = now - Duration::days(1);
+        params.not_after = not_after;
+
+        let cert = match Certificate::from_params(params) {
+            Ok(c) => c,
+            Err(e) => return Err(HttpUtilError::CertificateGenerateError(e.to_string())),
+        };
+        let cert_pem = match cert.serialize_pem_with_signer(&self.cert) {
+            Ok(p) => p,
+            Err(e) => return Err(HttpUtilError::CertificateSignError(e.to_string())),
+        };
+        Ok((cert_pem, cert.serialize_private_key_pem(), not_after))
+    }
+}
+
+// ** `a.api.example.com` shares the `*.api.example.com` entry with its siblings, anything shorter keeps its own,
+// ** browsers refuse a wildcard right under what may be a public suffix (`*.co.uk`)
+fn cache_key(host: &str) -> String {
+    if host.parse::<IpAddr>().is_ok() {
+        return host.to_string();
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < WILDCARD_MIN_LABELS {
+        return host.to_string();
+    }
+    format!("*.{}", labels[1..].join("."))
+}
+
+fn read_leaf(cert_path: &Path, key_path: &Path) -> Option<CachedCertificate> {
+    let cert_pem = fs::read_to_string(cert_path).ok()?;
+    let key_pem = fs::read_to_string(key_path).ok()?;
+    let key_pair = KeyPair::from_pem(&key_pem).ok()?;
+    let params = CertificateParams::from_ca_cert_pem(&cert_pem, key_pair).ok()?;
+    if !is_fresh(params.not_after) {
+        return None;
+    }
+    let identity = Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes()).ok()?;
+    Some(CachedCertificate {
+        identity,
+        not_after: params.not_after,
+    })
+}
+
+// ** drops expired or unreadable leaves, then the least recently written ones past `capacity`
+fn prune(dir: &Path, capacity: usize) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    let mut kept = Vec::new();
+    for entry in entries.flatten() {
+        let cert_path = entry.path();
+        if cert_path.extension().and_then(|e| e.to_str()) != Some(LEAF_CERT_EXTENSION) {
+            continue;
+        }
+        let key_path = cert_path.with_extension(LEAF_KEY_EXTENSION);
+        if read_leaf(&cert_path, &key_path).is_none() {
+            let _ = fs::remove_file(&cert_path);
+            let _ = fs::remove_file(&key_path);
+            continue;
+        }
+        let modified = entry.metadata().and_then(|m| m.modified()).ok();
+        kept.push((modified, cert_path, key_path));
+    }
+    if kept.len() <= capacity {
+        return;
+    }
+    kept.sort_by_key(|(modified, _, _)| *modified);
+    let excess = kept.len() - capacity;
+    for (_, cert_path, key_path) in kept.into_iter().take(excess) {
+        let _ = fs::remove_file(cert_path);
+        let _ = fs::remove_file(key_path);
+    }
+}
+
+// ** private keys are readable by the owner only, whoever else reads the ca key can intercept the user's tls
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // * the mode only applies to a new file, one left by an older version is tightened as well
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())
+}
+
+fn is_fresh(not_after: OffsetDateTime) -> bool {
+    not_after - OffsetDateTime::now_utc() > Duration::days(LEAF_RENEW_MARGIN_DAYS)
+}
+
+// ** read the first tls record (the ClientHello) from a client, or just the first bytes if it is not tls
+pub async fn read_client_hello<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; TLS_RECORD_HEADER_LEN];
+    stream.read_exact(&mut buf).await?;
+    if buf[0] != TLS_HANDSHAKE_RECORD {
+        return Ok(buf);
+    }
+    let len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    buf.resize(TLS_RECORD_HEADER_LEN + len.min(TLS_MAX_RECORD_LEN), 0);
+    stream.read_exact(&mut buf[TLS_RECORD_HEADER_LEN..]).await?;
+    Ok(buf)
+}
+
+// ** server name from the ClientHello's server_name extension
+pub fn parse_sni(record: &[u8]) -> Option<String> {
+    if *record.first()? != TLS_HANDSHAKE_RECORD {
+        return None;
+    }
+    // * handshake type(1) length(3)
+    let mut p = TLS_RECORD_HEADER_LEN;
+    if *record.get(p)? != TLS_CLIENT_HELLO {
+        return None;
+    }
+    p += 4;
+    // * version(2) random(32)
+    p += 34;
+    // * session id, cipher suites, compression methods
+    p += 1 + *record.get(p)? as usize;
+    p += 2 + read_u16(record, p)? as usize;
+    p += 1 + *record.get(p)? as usize;
+
+    let extensions_end = (p + 2 + read_u16(record, p)? as usize).min(record.len());
+    p += 2;
+    while p + 4 <= extensions_end {
+        let extension_type = read_u16(record, p)?;
+        let extension_len = read_u16(record, p + 2)? as usize;
+        p += 4;
+        if extension_type == TLS_EXTENSION_SERVER_NAME {
+            // * list length(2) name type(1) name length(2) name
+            if *record.get(p + 2)? != TLS_SERVER_NAME_HOST {
+                return None;
+            }
+            let name_len = read_u16(record, p + 3)? as usize;
+            let name = record.get(p + 5..p + 5 + name_len)?;
+            return String::from_utf8(name.to_vec()).ok();
+        }
+        p += extension_len;
+    }
+    None
+}
+
+fn read_u16(b: &[u8], p: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*b.get(p)?, *b.get(p + 1)?]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // * captured from `openssl s_client -servername example.com -tls1_2`
+    const CLIENT_HELLO: &[u8] = &[
+        0x16, 0x03, 0x01, 0x00, 0xca, 0x01, 0x00, 0x00, 0xc6, 0x03, 0x03, 0xff, 0x71, 0x0b, 0xd0,
+        0x86, 0xe1, 0x64, 0xc7, 0xfe, 0x74, 0xac, 0xff, 0x2b, 0xed, 0x71, 0xcd, 0xcb, 0x6e, 0x9a,
+        0x67, 0x0c, 0xd2, 0xf4, 0x71, 0x38, 0x40, 0xc1, 0xd3, 0xf0, 0x2d, 0x92, 0x56, 0x00, 0x00,
+        0x36, 0xc0, 0x2c, 0xc0, 0x30, 0x00, 0x9f, 0xcc, 0xa9, 0xcc, 0xa8, 0xcc, 0xaa, 0xc0, 0x2b,
+        0xc0, 0x2f, 0x00, 0x9e, 0xc0, 0x24, 0xc0, 0x28, 0x00, 0x6b, 0xc0, 0x23, 0xc0, 0x27, 0x00,
+        0x67, 0xc0, 0x0a, 0xc0, 0x14, 0x00, 0x39, 0xc0, 0x09, 0xc0, 0x13, 0x00, 0x33, 0x00, 0x9d,
+        0x00, 0x9c, 0x00, 0x3d, 0x00, 0x3c, 0x00, 0x35, 0x00, 0x2f, 0x01, 0x00, 0x00, 0x67, 0xff,
+        0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0x65, 0x78,
+        0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0x00, 0x0b, 0x00, 0x04, 0x03, 0x00,
+        0x01, 0x02, 0x00, 0x0a, 0x00, 0x0c, 0x00, 0x0a, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x1e, 0x00,
+        0x18, 0x00, 0x19, 0x00, 0x16, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00, 0x00, 0x0d, 0x00, 0x2a,
+        0x00, 0x28, 0x04, 0x03, 0x05, 0x03, 0x06, 0x03, 0x08, 0x07, 0x08, 0x08, 0x08, 0x09, 0x08,
+        0x0a, 0x08, 0x0b, 0x08, 0x04, 0x08, 0x05, 0x08, 0x06, 0x04, 0x01, 0x05, 0x01, 0x06, 0x01,
+        0x03, 0x03, 0x03, 0x01, 0x03, 0x02, 0x04, 0x02, 0x05, 0x02, 0x06, 0x02,
+    ];
+
+    #[tokio::test]
+    async fn reads_the_whole_client_hello_record() {
+        let mut stream = [CLIENT_HELLO, b"GET / HTTP/1.1"].concat();
+        let record = read_client_hello(&mut stream.as_slice()).await.unwrap();
+        assert_eq!(record, CLIENT_HELLO);
+        stream[0] = b'G';
+        let record = read_client_hello(&mut stream.as_slice()).await.unwrap();
+        assert_eq!(record.len(), TLS_RECORD_HEADER_LEN);
+    }
+
+    #[test]
+    fn sni_of_a_captured_client_hello() {
+        assert_eq!(parse_sni(CLIENT_HELLO).as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn no_sni_from_a_truncated_client_hello() {
+        let name_at = CLIENT_HELLO
+            .windows(11)
+            .position(|w| w == b"example.com")
+            .unwrap();
+        for len in [0, 1, TLS_RECORD_HEADER_LEN, 40, name_at, name_at + 5] {
+            assert_eq!(parse_sni(&CLIENT_HELLO[..len]), None, "{len}");
+        }
+        assert_eq!(parse_sni(b"GET / HTTP/1.1"), None);
+    }
+
+    #[test]
+    fn cache_key_shares_a_wildcard_only_from_four_labels() {
+        assert_eq!(cache_key("::1"), "::1");
+        assert_eq!(cache_key("2001:db8::1"), "2001:db8::1");
+        assert_eq!(cache_key("127.0.0.1"), "127.0.0.1");
+        assert_eq!(cache_key("localhost"), "localhost");
+        assert_eq!(cache_key("www.example.co.uk"), "*.example.co.uk");
+        assert_eq!(cache_key("api.example.com"), "api.example.com");
+        assert_eq!(cache_key("a.api.example.com"), "*.api.example.com");
+        assert_eq!(cache_key("b.api.example.com"), "*.api.example.com");
+    }
+}
@@ -13,5 +13,5 @@ pub trait VersionMethods {
 pub trait HeaderMapMethods {
     async fn from_json(json_data: String) -> Result<HeaderMap, HttpUtilError>;
     async fn json(&self, id: Option<&Uuid>) -> Result<String, HttpUtilError>;
-    fn check_encoding(&self) -> Result<(), HttpUtilError>;
+    fn retain_supported_encodings(&mut self);
 }
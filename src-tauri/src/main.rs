@@ -1,11 +1,25 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod commands;
+mod config;
+mod har;
+mod history;
 mod http_util;
+mod project;
 mod proxy;
 
-use proxy::run_proxy_server;
-use std::sync::{Arc, Mutex};
+use config::{Config, CONFIG_FILE_NAME};
+use http_util::{
+    intercept::{InterceptBroker, InterceptKind},
+    tls::{CertificateAuthority, CertificateCache, LEAF_CACHE_CAPACITY},
+};
+use project::{Project, TEMPORARY_PROJECT_DIR};
+use proxy::{
+    filter::InterceptFilter, listener::ListenerManager, repeater::Repeater, replace::Replacer,
+    scope::Scope, upstream::UpstreamClient, ProxyContext,
+};
+use std::sync::{Arc, Mutex, RwLock};
 use tauri::Manager;
 
 #[tokio::main]
@@ -15,12 +29,66 @@ async fn main() {
         .setup(|app| {
             // * proxy
             let pilot_state = Arc::new(Mutex::new(false));
-            let pilot_state_alt = pilot_state.clone();
-            let proxy_app_handle = app.app_handle();
+            let data_dir = match app.path_resolver().app_data_dir() {
+                Some(d) => d,
+                None => return Err("failed to resolve app data directory".into()),
+            };
+            let ca_dir = data_dir.join("ca");
+            let ca = CertificateAuthority::load_or_generate(&ca_dir)?.with_cache(
+                CertificateCache::new(LEAF_CACHE_CAPACITY, Some(ca_dir.join("leaves"))),
+            );
+            let config_path = match app.path_resolver().app_config_dir() {
+                Some(d) => d.join(CONFIG_FILE_NAME),
+                None => return Err("failed to resolve app config directory".into()),
+            };
+            // * a session starts in a temporary project seeded with the app defaults
+            let config = Config::load(&config_path)?;
+            let temporary_dir = data_dir.join(TEMPORARY_PROJECT_DIR);
+            Project::remove_temporary(&temporary_dir);
+            let project = Project::temporary(&temporary_dir, &config)?;
+            let intercept_filter = InterceptFilter::new(&config.intercept_rules)?;
+            let scope = Scope::new(&config.scope)?;
+            let replacer = Replacer::new(&config.replace_rules)?;
+            let upstream = UpstreamClient::new(&config.upstream)?;
+            let proxy_context = ProxyContext {
+                pilot_state: pilot_state.clone(),
+                ca: Arc::new(RwLock::new(ca)),
+                config: Arc::new(RwLock::new(config)),
+                config_path,
+                intercept: Arc::new(InterceptBroker::new()),
+                intercept_filter: Arc::new(RwLock::new(intercept_filter)),
+                scope: Arc::new(RwLock::new(scope)),
+                replacer: Arc::new(RwLock::new(Arc::new(replacer))),
+                project: Arc::new(RwLock::new(project)),
+                repeater: Arc::new(Repeater::new()),
+                upstream: Arc::new(RwLock::new(Arc::new(upstream))),
+            };
+            app.manage(proxy_context.clone());
+            app.manage(ListenerManager::new());
 
-            tokio::spawn(async move {
-                run_proxy_server(pilot_state_alt, proxy_app_handle).await;
-            });
+            let listeners = app.state::<ListenerManager>();
+            listeners.start_enabled(&proxy_context, &app.app_handle());
+
+            // * edited exchanges coming back over events are routed by their pair id
+            for (event, kind) in [
+                ("pilot-send-request", InterceptKind::Request),
+                ("pilot-send-response", InterceptKind::Response),
+            ] {
+                let broker = proxy_context.intercept.clone();
+                let app_handle = app.app_handle();
+                app.listen_global(event, move |event| {
+                    let payload = match event.payload() {
+                        Some(p) => p,
+                        None => {
+                            println!("received empty {:?} from frontend", kind);
+                            return;
+                        }
+                    };
+                    if let Err(e) = broker.resolve_payload(&app_handle, kind, payload) {
+                        println!("proxy error{}", e);
+                    }
+                });
+            }
 
             app.listen_global("pilot-state", move |event| {
                 let mut pilot_state = pilot_state.lock().unwrap();
@@ -35,7 +103,46 @@ async fn main() {
             });
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![])
+        .invoke_handler(tauri::generate_handler![
+            commands::get_ca_certificate,
+            commands::export_ca_certificate,
+            commands::rotate_ca_certificate,
+            commands::get_listeners,
+            commands::add_listener,
+            commands::remove_listener,
+            commands::start_listener,
+            commands::stop_listener,
+            commands::update_listener,
+            commands::get_intercepted,
+            commands::reorder_intercepted,
+            commands::resolve_intercepted,
+            commands::get_intercept_timeout,
+            commands::set_intercept_timeout,
+            commands::get_intercept_rules,
+            commands::set_intercept_rules,
+            commands::get_scope,
+            commands::set_scope,
+            commands::get_replace_rules,
+            commands::set_replace_rules,
+            commands::get_upstream_config,
+            commands::set_upstream_config,
+            commands::test_upstream_proxy,
+            commands::get_history,
+            commands::get_history_entry,
+            commands::get_websocket_frames,
+            commands::delete_history_entries,
+            commands::clear_history,
+            commands::export_har,
+            commands::import_har,
+            commands::repeater_send,
+            commands::get_repeater_history,
+            commands::close_repeater_tab,
+            commands::get_project,
+            commands::new_project,
+            commands::open_project,
+            commands::save_project_as,
+            commands::close_project,
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,170 @@
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::history::HistoryStore;
+
+pub const PROJECT_FILE_EXTENSION: &str = "rsproxy";
+pub const TEMPORARY_PROJECT_DIR: &str = "temporary";
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum ProjectError {
+    #[error(" >>> failed to create project >>> `{0}`")]
+    CreateError(String),
+    #[error(" >>> failed to open project >>> `{0}`")]
+    OpenError(String),
+    #[error(" >>> failed to save project >>> `{0}`")]
+    SaveError(String),
+    #[error(" >>> failed to close project >>> `{0}`")]
+    CloseError(String),
+}
+
+#[derive(Serialize, Clone)]
+pub struct ProjectInfo {
+    pub path: String,
+    pub temporary: bool,
+}
+
+// ** one sqlite file holds the traffic and the settings, every change is written right away
+// ** so there is nothing to "save" besides saving as another file
+pub struct Project {
+    pub path: PathBuf,
+    pub temporary: bool,
+    pub history: Arc<HistoryStore>,
+}
+
+impl Project {
+    pub fn create(path: &Path, config: &Config) -> Result<Self, ProjectError> {
+        if path.exists() {
+            return Err(ProjectError::CreateError(format!(
+                "{} already exists",
+                path.display()
+            )));
+        }
+        let project = match Self::open_file(path, false) {
+            Ok(p) => p,
+            Err(e) => return Err(ProjectError::CreateError(e.to_string())),
+        };
+        match project.save_config(config) {
+            Ok(_) => Ok(project),
+            Err(e) => Err(ProjectError::CreateError(e.to_string())),
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, ProjectError> {
+        if !path.exists() {
+            return Err(ProjectError::OpenError(format!(
+                "{} does not exist",
+                path.display()
+            )));
+        }
+        Self::open_file(path, false)
+    }
+
+    // ** used whenever no project is chosen, removed again on close
+    pub fn temporary(dir: &Path, config: &Config) -> Result<Self, ProjectError> {
+        let name = format!("{}.{}", uuid::Uuid::new_v4(), PROJECT_FILE_EXTENSION);
+        let project = match Self::open_file(&dir.join(name), true) {
+            Ok(p) => p,
+            Err(e) => return Err(ProjectError::CreateError(e.to_string())),
+        };
+        match project.save_config(config) {
+            Ok(_) => Ok(project),
+            Err(e) => Err(ProjectError::CreateError(e.to_string())),
+        }
+    }
+
+    // ** temporary projects left behind by a crash
+    pub fn remove_temporary(dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                println!("failed to remove temporary project: {}", e);
+            }
+        }
+    }
+
+    fn open_file(path: &Path, temporary: bool) -> Result<Self, ProjectError> {
+        let history = match HistoryStore::open(path) {
+            Ok(h) => h,
+            Err(e) => return Err(ProjectError::OpenError(e.to_string())),
+        };
+        Ok(Project {
+            path: path.to_path_buf(),
+            temporary,
+            history: Arc::new(history),
+        })
+    }
+
+    pub fn info(&self) -> ProjectInfo {
+        ProjectInfo {
+            path: self.path.to_string_lossy().to_string(),
+            temporary: self.temporary,
+        }
+    }
+
+    // ** `None` for a file that was never given settings, the caller falls back to defaults
+    pub fn load_config(&self) -> Result<Option<Config>, ProjectError> {
+        let json = match self.history.read_settings() {
+            Ok(Some(j)) => j,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(ProjectError::OpenError(e.to_string())),
+        };
+        match serde_json::from_str(&json) {
+            Ok(c) => Ok(Some(c)),
+            Err(e) => Err(ProjectError::OpenError(e.to_string())),
+        }
+    }
+
+    pub fn save_config(&self, config: &Config) -> Result<(), ProjectError> {
+        let json = match serde_json::to_string(config) {
+            Ok(j) => j,
+            Err(e) => return Err(ProjectError::SaveError(e.to_string())),
+        };
+        match self.history.write_settings(&json) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ProjectError::SaveError(e.to_string())),
+        }
+    }
+
+    pub fn save_as(&self, path: &Path) -> Result<Self, ProjectError> {
+        if path.exists() {
+            return Err(ProjectError::SaveError(format!(
+                "{} already exists",
+                path.display()
+            )));
+        }
+        if let Err(e) = self.history.copy_to(path) {
+            return Err(ProjectError::SaveError(e.to_string()));
+        }
+        Self::open(path)
+    }
+
+    // ** the connection goes away with the last handle, a temporary file goes with it
+    pub fn close(self) -> Result<(), ProjectError> {
+        if !self.temporary {
+            return Ok(());
+        }
+        // * handlers still holding the store would write to an unlinked file, or keep it locked on windows
+        match Arc::try_unwrap(self.history) {
+            Ok(history) => drop(history),
+            Err(history) => {
+                history.discard_on_drop(&self.path);
+                return Ok(());
+            }
+        }
+        match fs::remove_file(&self.path) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ProjectError::CloseError(e.to_string())),
+        }
+    }
+}
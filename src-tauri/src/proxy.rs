@@ -1,142 +1,468 @@
-use hyper::Server;
-use hyper_tls::HttpsConnector;
+pub mod chain;
+mod error;
+pub mod filter;
+pub mod listener;
+pub mod repeater;
+pub mod replace;
+pub mod reverse;
+pub mod scope;
+mod socks;
+mod transparent;
+mod tunnel;
+pub mod upstream;
+pub mod websocket;
+
+use hyper::body::HttpBody;
 use std::{
-    convert::Infallible,
-    io::Read,
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
 };
-use tauri::AppHandle;
+use tauri::{AppHandle, Manager};
 
-use crate::http_util::{self, traits::HeaderMapMethods};
+use crate::config::Config;
+use crate::history::{now_millis, HistoryStore, RecordedRequest, RecordedResponse};
+use crate::http_util::{
+    header::pair_id_string,
+    intercept::{InterceptAction, InterceptBroker},
+    request::{copy_request, RequestForFront},
+    response::{copy_response, ResponseForFront},
+    tls::CertificateAuthority,
+    traits::HeaderMapMethods,
+};
+use crate::project::{Project, ProjectError};
+use error::ProxyError;
+use filter::{Direction, InterceptFilter, Message};
+use repeater::Repeater;
+use replace::Replacer;
+use scope::{OutOfScope, Scope};
+use upstream::UpstreamClient;
+use websocket::FramePolicy;
 
-pub async fn run_proxy_server(pilot_state: Arc<Mutex<bool>>, app_handle: AppHandle) {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    let make_service = hyper::service::make_service_fn(move |_conn| {
-        let app_handle = app_handle.clone();
-        let pilot_state = pilot_state.clone();
-        async move {
-            Ok::<_, Infallible>(hyper::service::service_fn(
-                move |request: hyper::Request<hyper::Body>| {
-                    let app_handle = app_handle.clone();
-                    let pilot_state = pilot_state.clone();
-                    async move { Ok::<_, Infallible>(handle(request, pilot_state, app_handle).await) }
-                },
-            ))
+#[derive(Clone)]
+pub struct ProxyContext {
+    pub pilot_state: Arc<Mutex<bool>>,
+    pub ca: Arc<RwLock<CertificateAuthority>>,
+    pub config: Arc<RwLock<Config>>,
+    pub config_path: PathBuf,
+    pub intercept: Arc<InterceptBroker>,
+    pub intercept_filter: Arc<RwLock<InterceptFilter>>,
+    pub scope: Arc<RwLock<Scope>>,
+    pub replacer: Arc<RwLock<Arc<Replacer>>>,
+    pub project: Arc<RwLock<Project>>,
+    pub repeater: Arc<Repeater>,
+    pub upstream: Arc<RwLock<Arc<UpstreamClient>>>,
+}
+
+// ** address of the client behind a request, set by the listener that accepted it
+#[derive(Clone, Copy)]
+pub struct ClientAddr(pub SocketAddr);
+
+impl ProxyContext {
+    // ** settings belong to the open project, a temporary one also keeps them as the app defaults
+    pub fn save_config(&self) -> Result<(), ProjectError> {
+        let config = self.config.read().unwrap();
+        let project = self.project.read().unwrap();
+        project.save_config(&config)?;
+        if project.temporary {
+            if let Err(e) = config.save(&self.config_path) {
+                return Err(ProjectError::SaveError(e.to_string()));
+            }
         }
-    });
-    let server = Server::bind(&addr).serve(make_service).await;
+        Ok(())
+    }
+
+    // ** everything is compiled first, so a bad rule leaves the running settings untouched
+    pub fn apply_config(&self, config: Config) -> Result<(), ProxyError> {
+        let intercept_filter = InterceptFilter::new(&config.intercept_rules)?;
+        let scope = Scope::new(&config.scope)?;
+        let replacer = Replacer::new(&config.replace_rules)?;
+        let upstream = UpstreamClient::new(&config.upstream)?;
+        *self.intercept_filter.write().unwrap() = intercept_filter;
+        *self.scope.write().unwrap() = scope;
+        *self.replacer.write().unwrap() = Arc::new(replacer);
+        *self.upstream.write().unwrap() = Arc::new(upstream);
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+
+    pub fn history(&self) -> Arc<HistoryStore> {
+        self.project.read().unwrap().history.clone()
+    }
 
-    if let Err(e) = server {
-        println!("error: {}", e);
+    // * requests in flight keep the client they started with, the new one takes over from the next
+    pub fn upstream(&self) -> Arc<UpstreamClient> {
+        self.upstream.read().unwrap().clone()
     }
 }
 
+const PROXY_ERROR_EVENT: &str = "proxy-error";
+
 async fn handle(
     request: hyper::Request<hyper::Body>,
-    shared_pilot_state: Arc<Mutex<bool>>,
+    context: ProxyContext,
     app_handle: AppHandle,
 ) -> hyper::Response<hyper::Body> {
     let pair_id = uuid::Uuid::new_v4();
-    println!("{:?}", request.method());
-    request.headers().check_encoding().unwrap();
-
-    let request = if pilot_state(shared_pilot_state.clone()) {
-        let rq_front = match http_util::request::RequestForFront::from_hyper(request, Some(&pair_id)).await
-        {
-            Ok(rq) => rq,
-            Err(e) => {
-                panic!("proxy error: {}", e);
+    let wants_json = accepts_json(request.headers());
+    let version = request.version();
+    // * taken once, an exchange spanning a project switch stays in the project it started in
+    let history = context.history();
+
+    let mut response = match exchange(request, &pair_id, &history, &context, &app_handle).await {
+        Ok(rs) => rs,
+        Err(e) => {
+            println!("proxy error{}", e);
+            let pair_id = pair_id_string(&pair_id);
+            emit_error(&app_handle, &e, &pair_id);
+            let (id, error) = (pair_id.clone(), e.to_string());
+            if let Err(e) = history
+                .clone()
+                .run_blocking(move |h| h.record_error(&id, &error))
+                .await
+            {
+                println!("proxy error{}", e);
             }
-        };
+            e.to_response(&pair_id, wants_json)
+        }
+    };
+    // * history keeps the upstream version, the client is answered in the one it spoke
+    *response.version_mut() = version;
+    response
+}
 
-        if let Err(e) = rq_front.send_to_front(&app_handle).await {
-            panic!("proxy error: {}", e);
+async fn exchange(
+    mut request: hyper::Request<hyper::Body>,
+    pair_id: &uuid::Uuid,
+    history: &Arc<HistoryStore>,
+    context: &ProxyContext,
+    app_handle: &AppHandle,
+) -> Result<hyper::Response<hyper::Body>, ProxyError> {
+    request.headers_mut().retain_supported_encodings();
+    let pair_id_str = pair_id_string(pair_id);
+    let client_addr = request
+        .extensions()
+        .get::<ClientAddr>()
+        .map(|a| a.0.to_string());
+    let started_at = now_millis();
+    // * taken before the request is copied around, the copies do not carry it
+    let client_upgrade = match websocket::is_upgrade_request(request.headers()) {
+        true => {
+            // * the bridge cannot inflate permessage-deflate frames, so no extension is negotiated
+            request
+                .headers_mut()
+                .remove(hyper::header::SEC_WEBSOCKET_EXTENSIONS);
+            Some(hyper::upgrade::on(&mut request))
         }
-        let m_rq_front = match rq_front.wait_for_modification(&app_handle).await {
-            Ok(rq) => rq,
-            Err(e) => {
-                panic!("proxy error >>> {}", e);
+        false => None,
+    };
+
+    // * rewritten before anything else, so scope, history and the pilot see what is really sent
+    let replacer = context.replacer.read().unwrap().clone();
+    let request = replacer.apply_request(request).await?;
+
+    let uri = request.uri().clone();
+    let method = request.method().clone();
+    // * only in-scope traffic can be held, the rest is recorded, passed or refused
+    let (in_scope, out_of_scope) = {
+        let scope = context.scope.read().unwrap();
+        (scope.contains(&uri), scope.out_of_scope)
+    };
+    if !in_scope && out_of_scope == OutOfScope::Block {
+        return Err(ProxyError::OutOfScopeError(uri.to_string()));
+    }
+    let record = in_scope || out_of_scope == OutOfScope::Record;
+
+    let timeout = context.config.read().unwrap().intercept_timeout();
+    let mut intercept_response = false;
+    let hold_request = in_scope
+        && should_intercept(
+            context,
+            &Message {
+                direction: Direction::Request,
+                uri: &uri,
+                method: &method,
+                headers: request.headers(),
+            },
+        );
+    let (request, rq_front) = if hold_request {
+        let (rq1, rq2) = copy_request(request).await?;
+        let rq_front = RequestForFront::from_hyper(rq1, Some(pair_id)).await?;
+        rq_front.send_to_front(app_handle).await?;
+        let decision = rq_front
+            .wait_for_decision(&context.intercept, app_handle, pair_id, timeout)
+            .await?;
+        intercept_response = decision.intercept_response;
+        match decision.action {
+            InterceptAction::Forward => (rq2, Some(rq_front)),
+            InterceptAction::ForwardModified { payload } => {
+                let m_rq_front = rq_front.modified(&payload)?;
+                // * an untouched request goes out exactly as the client sent it
+                if m_rq_front == rq_front {
+                    (rq2, Some(rq_front))
+                } else {
+                    (m_rq_front.clone().to_hyper().await?, Some(m_rq_front))
+                }
             }
-        };
-        match m_rq_front.to_hyper().await {
-            Ok(rq) => rq,
-            Err(e) => {
-                panic!("proxy error >>> {}", e);
+            InterceptAction::Drop { status } => {
+                let size = body_size(rq2.body());
+                store_request(
+                    history,
+                    &pair_id_str,
+                    &rq_front,
+                    size,
+                    client_addr,
+                    started_at,
+                )
+                .await;
+                let rs_front =
+                    ResponseForFront::from_hyper(dropped_response(status)?, Some(pair_id)).await?;
+                rs_front.send_to_front(app_handle).await?;
+                store_response(history, &pair_id_str, &rs_front, 0, None, None).await;
+                return dropped_response(status);
             }
         }
+    } else if record {
+        let (rq1, rq2) = copy_request(request).await?;
+        let rq_front = RequestForFront::from_hyper(rq1, Some(pair_id)).await?;
+        rq_front.send_to_front(app_handle).await?;
+        (rq2, Some(rq_front))
     } else {
-        let (rq1, rq2) = match http_util::request::copy_request(request).await {
-            Ok(t) => t,
-            Err(e) => {
-                panic!("proxy error >>> {}", e);
-            }
-        };
-        let rq_front = match http_util::request::RequestForFront::from_hyper(rq1, Some(&pair_id)).await {
-            Ok(rq) => rq,
-            Err(e) => {
-                panic!("proxy error >>> {}", e);
-            }
-        };
-        if let Err(e) = rq_front.send_to_front(&app_handle).await {
-            panic!("proxy error >>> {}", e);
-        }
-        rq2
+        (request, None)
     };
+    if let Some(rq_front) = &rq_front {
+        let size = body_size(request.body());
+        store_request(
+            history,
+            &pair_id_str,
+            rq_front,
+            size,
+            client_addr,
+            started_at,
+        )
+        .await;
+    }
 
-    let response = {
-        let https = HttpsConnector::new();
-        let client = hyper::Client::builder().build::<_, hyper::Body>(https);
-        let resp = client.request(request).await;
-        if resp.is_err() {
-            panic!("proxy error >>> {}", resp.unwrap_err());
-        }
-        resp.unwrap()
+    // * frames are held by the rules that match their handshake, whether or not the pilot is on yet
+    let hold_to_server = client_upgrade.is_some()
+        && in_scope
+        && context.intercept_filter.read().unwrap().matches(&Message {
+            direction: Direction::Request,
+            uri: &uri,
+            method: &method,
+            headers: request.headers(),
+        });
+
+    let (mut response, connection) = context.upstream().send(request).await?;
+    let server_upgrade = match client_upgrade.is_some()
+        && response.status() == hyper::StatusCode::SWITCHING_PROTOCOLS
+    {
+        true => Some(hyper::upgrade::on(&mut response)),
+        false => None,
     };
 
-    if pilot_state(shared_pilot_state.clone()) {
-        let rs_front =
-            match http_util::response::ResponseForFront::from_hyper(response, Some(&pair_id)).await {
-                Ok(rs) => rs,
-                Err(e) => {
-                    panic!("proxy error >>> {}", e);
+    let response = replacer.apply_response(response).await?;
+    let hold_to_client = server_upgrade.is_some()
+        && in_scope
+        && context.intercept_filter.read().unwrap().matches(&Message {
+            direction: Direction::Response,
+            uri: &uri,
+            method: &method,
+            headers: response.headers(),
+        });
+    let hold_response = intercept_response
+        || in_scope
+            && should_intercept(
+                context,
+                &Message {
+                    direction: Direction::Response,
+                    uri: &uri,
+                    method: &method,
+                    headers: response.headers(),
+                },
+            );
+    let (response, rs_front) = if hold_response {
+        let (rs1, rs2) = copy_response(response).await?;
+        let rs_front = ResponseForFront::from_hyper(rs1, Some(pair_id)).await?;
+        rs_front.send_to_front(app_handle).await?;
+        let decision = rs_front
+            .wait_for_decision(&context.intercept, app_handle, pair_id, timeout)
+            .await?;
+        match decision.action {
+            InterceptAction::Forward => (rs2, Some(rs_front)),
+            InterceptAction::ForwardModified { payload } => {
+                let m_rs_front = rs_front.modified(&payload)?;
+                // * re-encoding would change the bytes, so an untouched response is passed through
+                if m_rs_front == rs_front {
+                    (rs2, Some(rs_front))
+                } else {
+                    (m_rs_front.to_hyper().await?, Some(m_rs_front))
                 }
-            };
-        if let Err(e) = rs_front.send_to_front(&app_handle).await {
-            panic!("proxy error >>> {}", e);
-        }
-        let m_rs_front = match rs_front.wait_for_modification(&app_handle).await {
-            Ok(rs) => rs,
-            Err(e) => {
-                panic!("proxy error >>> {}", e);
             }
-        };
-        match m_rs_front.to_hyper().await {
-            Ok(rs) => rs,
-            Err(e) => {
-                panic!("proxy error >>> {}", e)
+            InterceptAction::Drop { status } => {
+                let d_rs_front =
+                    ResponseForFront::from_hyper(dropped_response(status)?, Some(pair_id)).await?;
+                (dropped_response(status)?, Some(d_rs_front))
             }
         }
+    } else if record {
+        let (rs1, rs2) = copy_response(response).await?;
+        let rs_front = ResponseForFront::from_hyper(rs1, Some(pair_id)).await?;
+        rs_front.send_to_front(app_handle).await?;
+        (rs2, Some(rs_front))
     } else {
-        let (rs1, rs2) = match http_util::response::copy_response(response).await {
-            Ok(t) => t,
-            Err(e) => {
-                panic!("proxy error >>> {}", e)
-            }
+        (response, None)
+    };
+    if let Some(rs_front) = &rs_front {
+        let size = body_size(response.body());
+        store_response(
+            history,
+            &pair_id_str,
+            rs_front,
+            size,
+            connection.remote_addr,
+            Some(connection.reused),
+        )
+        .await;
+    }
+
+    // * the sockets only switch once the 101 has reached the client, so the bridge waits on its own task
+    if let (Some(client_upgrade), Some(server_upgrade)) = (client_upgrade, server_upgrade) {
+        let policy = FramePolicy {
+            pair_id: pair_id_str,
+            record,
+            history: history.clone(),
+            hold_to_server,
+            hold_to_client,
         };
-        let rs_front = match http_util::response::ResponseForFront::from_hyper(rs1, Some(&pair_id)).await {
-            Ok(rs) => rs,
-            Err(e) => {
-                panic!("proxy error >>> {}", e)
+        let context = context.clone();
+        let app_handle = app_handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                websocket::bridge(client_upgrade, server_upgrade, policy, context, app_handle).await
+            {
+                println!("proxy error{}", e);
             }
-        };
-        if let Err(e) = rs_front.send_to_front(&app_handle).await {
-            panic!("proxy error >>> {}", e)
+        });
+    }
+    Ok(response)
+}
+
+// ** history failures are logged, they never break the exchange itself
+async fn store_request(
+    history: &Arc<HistoryStore>,
+    pair_id: &str,
+    rq_front: &RequestForFront,
+    request_size: u64,
+    client_addr: Option<String>,
+    started_at: i64,
+) {
+    let request = match serde_json::to_string(rq_front) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("failed to serialize request for history: {}", e);
+            return;
         }
-        rs2
+    };
+    let (pair_id, method, url) = (
+        pair_id.to_string(),
+        rq_front.method.clone(),
+        rq_front.url.clone(),
+    );
+    let result = history.clone().run_blocking(move |h| {
+        h.record_request(&RecordedRequest {
+            pair_id: &pair_id,
+            method: &method,
+            url: &url,
+            request: &request,
+            client_addr,
+            request_size,
+            started_at,
+        })
+    });
+    if let Err(e) = result.await {
+        println!("proxy error{}", e);
+    }
+}
+
+async fn store_response(
+    history: &Arc<HistoryStore>,
+    pair_id: &str,
+    rs_front: &ResponseForFront,
+    response_size: u64,
+    upstream_addr: Option<String>,
+    connection_reused: Option<bool>,
+) {
+    let response = match serde_json::to_string(rs_front) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("failed to serialize response for history: {}", e);
+            return;
+        }
+    };
+    let (pair_id, status, finished_at) = (pair_id.to_string(), rs_front.status, now_millis());
+    let result = history.clone().run_blocking(move |h| {
+        h.record_response(&RecordedResponse {
+            pair_id: &pair_id,
+            status,
+            response: &response,
+            upstream_addr,
+            connection_reused,
+            response_size,
+            finished_at,
+            timings: None,
+        })
+    });
+    if let Err(e) = result.await {
+        println!("proxy error{}", e);
+    }
+}
+
+// ** bodies here are either fully buffered copies or straight from the wire
+fn body_size(body: &hyper::Body) -> u64 {
+    let hint = HttpBody::size_hint(body);
+    hint.exact().unwrap_or(hint.lower())
+}
+
+// ** what the client gets for a message dropped in the pilot
+fn dropped_response(status: u16) -> Result<hyper::Response<hyper::Body>, ProxyError> {
+    let status = match hyper::StatusCode::from_u16(status) {
+        Ok(s) => s,
+        Err(e) => return Err(ProxyError::FrontendError(e.to_string())),
+    };
+    let mut response = hyper::Response::new(hyper::Body::empty());
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+fn accepts_json(headers: &hyper::HeaderMap) -> bool {
+    match headers.get(hyper::header::ACCEPT) {
+        Some(v) => v
+            .to_str()
+            .is_ok_and(|v| v.contains("application/json") && !v.contains("text/html")),
+        None => false,
+    }
+}
+
+fn emit_error(app_handle: &AppHandle, e: &ProxyError, pair_id: &str) {
+    let report = match serde_json::to_string(&e.report(pair_id)) {
+        Ok(r) => r,
+        Err(e) => {
+            println!("failed to serialize proxy error: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = app_handle.emit_all(PROXY_ERROR_EVENT, report) {
+        println!("failed to emit proxy error: {}", e);
     }
 }
 
 fn pilot_state(shared_pilot_state: Arc<Mutex<bool>>) -> bool {
     *shared_pilot_state.lock().unwrap()
 }
+
+// ** the pilot switch turns interception on, the rules pick what is held
+fn should_intercept(context: &ProxyContext, message: &Message) -> bool {
+    pilot_state(context.pilot_state.clone())
+        && context.intercept_filter.read().unwrap().matches(message)
+}
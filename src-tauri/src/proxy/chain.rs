@@ -0,0 +1,533 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::error::ProxyError;
+use super::filter::glob_to_regex;
+use super::socks::{
+    reply_message, SOCKS_ATYP_DOMAIN, SOCKS_ATYP_IPV4, SOCKS_ATYP_IPV6, SOCKS_AUTH_VERSION,
+    SOCKS_CMD_CONNECT, SOCKS_NO_ACCEPTABLE, SOCKS_NO_AUTH, SOCKS_USER_PASS, SOCKS_VERSION,
+};
+
+// * a proxy answering CONNECT with more than this is not one we understand
+const MAX_CONNECT_RESPONSE: usize = 8192;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProxyKind {
+    // * tunnels every connection with CONNECT, plain http included
+    Http,
+    Socks5,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpstreamProxy {
+    pub kind: UpstreamProxyKind,
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+// ** the first enabled rule whose pattern matches the destination host picks the proxy
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpstreamProxyRule {
+    pub id: String,
+    pub enabled: bool,
+    // * globs, `*.corp.example.com`, no pattern matches every host
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    pub proxy: UpstreamProxy,
+}
+
+// ** destinations are matched against the rules unless a bypass glob matches first
+pub struct ProxyRouter {
+    rules: Vec<(Vec<Regex>, UpstreamProxy)>,
+    bypass: Vec<Regex>,
+}
+
+impl ProxyRouter {
+    pub fn new(rules: &[UpstreamProxyRule], bypass: &[String]) -> Result<Self, ProxyError> {
+        let mut compiled = Vec::new();
+        for rule in rules.iter().filter(|r| r.enabled) {
+            if rule.proxy.host.is_empty() {
+                return Err(ProxyError::InvalidChainRuleError(format!(
+                    "rule {} has no proxy host",
+                    rule.id
+                )));
+            }
+            let mut hosts = Vec::new();
+            for h in &rule.hosts {
+                hosts.push(compile_glob(h)?);
+            }
+            compiled.push((hosts, rule.proxy.clone()));
+        }
+        let mut compiled_bypass = Vec::new();
+        for b in bypass {
+            compiled_bypass.push(compile_glob(b)?);
+        }
+        Ok(ProxyRouter {
+            rules: compiled,
+            bypass: compiled_bypass,
+        })
+    }
+
+    // * a router that sends everything through one proxy
+    pub fn single(proxy: UpstreamProxy) -> Self {
+        ProxyRouter {
+            rules: vec![(Vec::new(), proxy)],
+            bypass: Vec::new(),
+        }
+    }
+
+    pub fn route(&self, host: &str) -> Option<&UpstreamProxy> {
+        if self.bypass.iter().any(|b| b.is_match(host)) {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|(hosts, _)| hosts.is_empty() || hosts.iter().any(|h| h.is_match(host)))
+            .map(|(_, proxy)| proxy)
+    }
+}
+
+fn compile_glob(glob: &str) -> Result<Regex, ProxyError> {
+    match glob_to_regex(glob) {
+        Ok(r) => Ok(r),
+        Err(e) => Err(ProxyError::InvalidChainRuleError(e.to_string())),
+    }
+}
+
+impl UpstreamProxy {
+    fn credentials(&self) -> Option<(&str, &str)> {
+        match &self.username {
+            Some(u) => Some((u, self.password.as_deref().unwrap_or(""))),
+            None => None,
+        }
+    }
+
+    // ** after this the stream talks to `host:port` as if it were connected directly
+    pub async fn open_tunnel(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), ProxyError> {
+        match self.kind {
+            UpstreamProxyKind::Http => http_connect(stream, host, port, self.credentials()).await,
+            UpstreamProxyKind::Socks5 => {
+                socks5_connect(stream, host, port, self.credentials()).await
+            }
+        }
+    }
+}
+
+async fn http_connect(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<(), ProxyError> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+    if let Some((user, password)) = credentials {
+        let token = STANDARD.encode(format!("{}:{}", user, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    if let Err(e) = stream.write_all(request.as_bytes()).await {
+        return Err(ProxyError::ChainConnectError(e.to_string()));
+    }
+
+    // * read byte by byte, whatever follows the head already belongs to the tunnel
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > MAX_CONNECT_RESPONSE {
+            return Err(ProxyError::ChainConnectError(
+                "CONNECT response head is too large".to_string(),
+            ));
+        }
+        match stream.read_u8().await {
+            Ok(b) => head.push(b),
+            Err(e) => return Err(ProxyError::ChainConnectError(e.to_string())),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or("");
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(ProxyError::ChainConnectError(format!(
+            "proxy refused CONNECT {}: {}",
+            authority, status_line
+        )))
+    }
+}
+
+// ** RFC 1928, with RFC 1929 username/password auth
+async fn socks5_connect(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<(), ProxyError> {
+    let greeting = match credentials {
+        Some(_) => vec![SOCKS_VERSION, 2, SOCKS_NO_AUTH, SOCKS_USER_PASS],
+        None => vec![SOCKS_VERSION, 1, SOCKS_NO_AUTH],
+    };
+    write(stream, &greeting).await?;
+    let choice = read(stream, 2).await?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(ProxyError::ChainConnectError(format!(
+            "not a socks5 proxy, version {}",
+            choice[0]
+        )));
+    }
+    match (choice[1], credentials) {
+        (SOCKS_NO_AUTH, _) => {}
+        (SOCKS_USER_PASS, Some((user, password))) => {
+            if user.len() > 255 || password.len() > 255 {
+                return Err(ProxyError::ChainConnectError(
+                    "socks5 username and password are limited to 255 bytes".to_string(),
+                ));
+            }
+            let mut auth = vec![SOCKS_AUTH_VERSION, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            write(stream, &auth).await?;
+            let status = read(stream, 2).await?;
+            if status[1] != 0 {
+                return Err(ProxyError::ChainConnectError(
+                    "socks5 authentication failed".to_string(),
+                ));
+            }
+        }
+        (SOCKS_NO_ACCEPTABLE, _) => {
+            return Err(ProxyError::ChainConnectError(
+                "socks5 proxy accepted none of the offered auth methods".to_string(),
+            ))
+        }
+        (m, _) => {
+            return Err(ProxyError::ChainConnectError(format!(
+                "socks5 proxy chose unexpected auth method {}",
+                m
+            )))
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(SOCKS_ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(SOCKS_ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(ProxyError::ChainConnectError(format!(
+                    "host name {} is too long for socks5",
+                    host
+                )));
+            }
+            request.push(SOCKS_ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    write(stream, &request).await?;
+
+    let reply = read(stream, 4).await?;
+    if reply[1] != 0 {
+        return Err(ProxyError::ChainConnectError(format!(
+            "socks5 proxy refused {}:{}: {}",
+            host,
+            port,
+            reply_message(reply[1])
+        )));
+    }
+    // * the bound address is of no use to us, but it has to be read off the stream
+    let address_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => read(stream, 1).await?[0] as usize,
+        t => {
+            return Err(ProxyError::ChainConnectError(format!(
+                "socks5 reply has unknown address type {}",
+                t
+            )))
+        }
+    };
+    read(stream, address_len + 2).await?;
+    Ok(())
+}
+
+async fn write(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), ProxyError> {
+    match stream.write_all(bytes).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ProxyError::ChainConnectError(e.to_string())),
+    }
+}
+
+async fn read(stream: &mut TcpStream, len: usize) -> Result<Vec<u8>, ProxyError> {
+    let mut buf = vec![0; len];
+    match stream.read_exact(&mut buf).await {
+        Ok(_) => Ok(buf),
+        Err(e) => Err(ProxyError::ChainConnectError(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // * the proxy's end of a connection to a stand-in on localhost
+    async fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connecting = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, accepted) = tokio::join!(connecting, listener.accept());
+        (client.unwrap(), accepted.unwrap().0)
+    }
+
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    async fn expect(stream: &mut TcpStream, bytes: &[u8]) {
+        let mut buf = vec![0; bytes.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, bytes);
+    }
+
+    #[tokio::test]
+    async fn http_connect_leaves_the_tunnel_bytes_on_the_stream() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            let head = read_head(&mut proxy).await;
+            proxy
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunnel")
+                .await
+                .unwrap();
+            head
+        };
+        let (result, head) = tokio::join!(
+            http_connect(&mut client, "example.com", 443, Some(("user", "pass"))),
+            stand_in
+        );
+        result.unwrap();
+        assert!(head.starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+        assert!(head.contains("Host: example.com:443\r\n"));
+        assert!(head.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+        expect(&mut client, b"tunnel").await;
+    }
+
+    #[tokio::test]
+    async fn http_connect_brackets_an_ipv6_destination() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            let head = read_head(&mut proxy).await;
+            proxy.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+            head
+        };
+        let (result, head) = tokio::join!(http_connect(&mut client, "::1", 8080, None), stand_in);
+        result.unwrap();
+        assert!(head.starts_with("CONNECT [::1]:8080 HTTP/1.1\r\n"));
+        assert!(!head.contains("Proxy-Authorization"));
+    }
+
+    #[tokio::test]
+    async fn http_connect_fails_on_407() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            read_head(&mut proxy).await;
+            proxy
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        };
+        let (result, _) =
+            tokio::join!(http_connect(&mut client, "example.com", 80, None), stand_in);
+        match result {
+            Err(ProxyError::ChainConnectError(e)) => assert!(e.contains("407")),
+            r => panic!("expected a refused CONNECT, got {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_to_an_ipv4_address() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(&mut proxy, &[SOCKS_VERSION, 1, SOCKS_NO_AUTH]).await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])
+                .await
+                .unwrap();
+            expect(
+                &mut proxy,
+                &[
+                    SOCKS_VERSION,
+                    SOCKS_CMD_CONNECT,
+                    0,
+                    SOCKS_ATYP_IPV4,
+                    10,
+                    0,
+                    0,
+                    1,
+                    0,
+                    80,
+                ],
+            )
+            .await;
+            proxy
+                .write_all(&[SOCKS_VERSION, 0, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            proxy.write_all(b"tunnel").await.unwrap();
+        };
+        let (result, _) = tokio::join!(socks5_connect(&mut client, "10.0.0.1", 80, None), stand_in);
+        result.unwrap();
+        expect(&mut client, b"tunnel").await;
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_to_a_domain_with_username_and_password() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(
+                &mut proxy,
+                &[SOCKS_VERSION, 2, SOCKS_NO_AUTH, SOCKS_USER_PASS],
+            )
+            .await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_USER_PASS])
+                .await
+                .unwrap();
+            expect(&mut proxy, b"\x01\x04user\x04pass").await;
+            proxy.write_all(&[SOCKS_AUTH_VERSION, 0]).await.unwrap();
+            expect(&mut proxy, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb").await;
+            // * a domain as the bound address, its length prefix has to be read too
+            proxy
+                .write_all(b"\x05\x00\x00\x03\x04host\x00\x50tunnel")
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::join!(
+            socks5_connect(&mut client, "example.com", 443, Some(("user", "pass"))),
+            stand_in
+        );
+        result.unwrap();
+        expect(&mut client, b"tunnel").await;
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_to_an_ipv6_address() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(&mut proxy, &[SOCKS_VERSION, 1, SOCKS_NO_AUTH]).await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])
+                .await
+                .unwrap();
+            let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0, SOCKS_ATYP_IPV6];
+            request.extend_from_slice(
+                &"2001:db8::1"
+                    .parse::<std::net::Ipv6Addr>()
+                    .unwrap()
+                    .octets(),
+            );
+            request.extend_from_slice(&8443u16.to_be_bytes());
+            expect(&mut proxy, &request).await;
+            let mut reply = vec![SOCKS_VERSION, 0, 0, SOCKS_ATYP_IPV6];
+            reply.extend_from_slice(&[0; 18]);
+            reply.extend_from_slice(b"tunnel");
+            proxy.write_all(&reply).await.unwrap();
+        };
+        let (result, _) = tokio::join!(
+            socks5_connect(&mut client, "2001:db8::1", 8443, None),
+            stand_in
+        );
+        result.unwrap();
+        expect(&mut client, b"tunnel").await;
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_fails_without_an_acceptable_method() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(&mut proxy, &[SOCKS_VERSION, 1, SOCKS_NO_AUTH]).await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE])
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::join!(socks5_connect(&mut client, "10.0.0.1", 80, None), stand_in);
+        assert!(matches!(result, Err(ProxyError::ChainConnectError(_))));
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_fails_on_rejected_credentials() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(
+                &mut proxy,
+                &[SOCKS_VERSION, 2, SOCKS_NO_AUTH, SOCKS_USER_PASS],
+            )
+            .await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_USER_PASS])
+                .await
+                .unwrap();
+            expect(&mut proxy, b"\x01\x04user\x05wrong").await;
+            proxy.write_all(&[SOCKS_AUTH_VERSION, 1]).await.unwrap();
+        };
+        let (result, _) = tokio::join!(
+            socks5_connect(&mut client, "10.0.0.1", 80, Some(("user", "wrong"))),
+            stand_in
+        );
+        match result {
+            Err(ProxyError::ChainConnectError(e)) => assert!(e.contains("authentication")),
+            r => panic!("expected an authentication failure, got {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_fails_when_the_proxy_refuses() {
+        let (mut client, mut proxy) = connected().await;
+        let stand_in = async {
+            expect(&mut proxy, &[SOCKS_VERSION, 1, SOCKS_NO_AUTH]).await;
+            proxy
+                .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])
+                .await
+                .unwrap();
+            let mut request = [0; 10];
+            proxy.read_exact(&mut request).await.unwrap();
+            // * 0x05, connection refused
+            proxy
+                .write_all(&[SOCKS_VERSION, 5, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        };
+        let (result, _) = tokio::join!(socks5_connect(&mut client, "10.0.0.1", 80, None), stand_in);
+        assert!(matches!(result, Err(ProxyError::ChainConnectError(_))));
+    }
+}
@@ -0,0 +1,177 @@
+use hyper::{header::CONTENT_TYPE, Body, Response, StatusCode};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::http_util::error::HttpUtilError;
+
+// * every variant ends in `Error`, as in HttpUtilError, so matches read the same across modules
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    // ** proxy.rs
+    #[error(" >>> invalid request >>> `{0}`")]
+    InvalidRequestError(String),
+    #[error(" >>> upstream request failed >>> `{0}`")]
+    UpstreamError(String),
+    #[error(" >>> upstream did not respond in time >>> `{0}`")]
+    UpstreamTimeoutError(String),
+    #[error(" >>> invalid upstream response >>> `{0}`")]
+    InvalidResponseError(String),
+    #[error(" >>> failed to exchange with frontend >>> `{0}`")]
+    FrontendError(String),
+    #[error(" >>> request is out of scope >>> `{0}`")]
+    OutOfScopeError(String),
+    #[error(" >>> failed to apply match and replace rules >>> `{0}`")]
+    ReplaceError(String),
+    // ** tunnel.rs
+    #[error(" >>> invalid CONNECT authority >>> `{0}`")]
+    InvalidAuthorityError(String),
+    #[error(" >>> failed to upgrade CONNECT request >>> `{0}`")]
+    UpgradeError(String),
+    #[error(" >>> failed to prepare tls for tunnel >>> `{0}`")]
+    TlsSetupError(String),
+    #[error(" >>> failed to accept tls connection from client >>> `{0}`")]
+    TlsAcceptError(String),
+    #[error(" >>> failed to serve tunneled connection >>> `{0}`")]
+    TunnelServeError(String),
+    #[error(" >>> failed to relay connection >>> `{0}`")]
+    RelayError(String),
+    // ** filter.rs
+    #[error(" >>> invalid intercept rule >>> `{0}`")]
+    InvalidRuleError(String),
+    // ** scope.rs
+    #[error(" >>> invalid scope >>> `{0}`")]
+    InvalidScopeError(String),
+    // ** replace.rs
+    #[error(" >>> invalid match and replace rule >>> `{0}`")]
+    InvalidReplaceRuleError(String),
+    // ** chain.rs
+    #[error(" >>> invalid upstream proxy >>> `{0}`")]
+    InvalidChainRuleError(String),
+    #[error(" >>> upstream proxy failed >>> `{0}`")]
+    ChainConnectError(String),
+    // ** reverse.rs
+    #[error(" >>> invalid reverse proxy route >>> `{0}`")]
+    InvalidReverseRouteError(String),
+    // ** socks.rs
+    #[error(" >>> socks handshake failed >>> `{0}`")]
+    SocksHandshakeError(String),
+    // ** transparent.rs
+    #[error(" >>> failed to recover original destination >>> `{0}`")]
+    OriginalDestinationError(String),
+    // ** upstream.rs
+    #[error(" >>> failed to set up upstream client >>> `{0}`")]
+    UpstreamSetupError(String),
+    // ** websocket.rs
+    #[error(" >>> failed to hold websocket frame >>> `{0}`")]
+    FrameInterceptError(String),
+    #[error(" >>> websocket connection failed >>> `{0}`")]
+    WebSocketError(String),
+    // ** listener.rs
+    #[error(" >>> failed to bind listener >>> `{0}`")]
+    BindError(String),
+    #[error(" >>> listener stopped with an error >>> `{0}`")]
+    ServeError(String),
+}
+
+#[derive(Serialize)]
+pub struct ErrorReport<'a> {
+    pub status: u16,
+    pub error: String,
+    pub pair_id: &'a str,
+}
+
+impl ProxyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequestError(_) | Self::InvalidAuthorityError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UpstreamTimeoutError(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::OutOfScopeError(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub fn report<'a>(&self, pair_id: &'a str) -> ErrorReport<'a> {
+        ErrorReport {
+            status: self.status().as_u16(),
+            error: self.to_string().trim_start_matches(" >>> ").to_string(),
+            pair_id,
+        }
+    }
+
+    // ** synthetic response sent to the client instead of the upstream one
+    pub fn to_response(&self, pair_id: &str, json: bool) -> Response<Body> {
+        let status = self.status();
+        let report = self.report(pair_id);
+        let (content_type, body) = if json {
+            (
+                "application/json",
+                serde_json::to_string(&report).unwrap_or_default(),
+            )
+        } else {
+            let reason = status.canonical_reason().unwrap_or("Error");
+            (
+                "text/html; charset=utf-8",
+                format!(
+                    "<!DOCTYPE html>\n<html>\n<head><title>{code} {reason}</title></head>\n<body>\n<h1>{code} {reason}</h1>\n<p>{message}</p>\n<hr>\n<p>rsproxy &middot; pair-id {pair_id}</p>\n</body>\n</html>\n",
+                    code = status.as_u16(),
+                    reason = reason,
+                    message = escape_html(&report.error),
+                    pair_id = pair_id,
+                ),
+            )
+        };
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, content_type.parse().unwrap());
+        response
+    }
+}
+
+impl From<HttpUtilError> for ProxyError {
+    fn from(e: HttpUtilError) -> Self {
+        let message = e.to_string();
+        match e {
+            HttpUtilError::BodyCopyError(_)
+            | HttpUtilError::BodyDecodeError(_)
+            | HttpUtilError::MakeSupportedEncodingError(_)
+            | HttpUtilError::UnsupportedEncodingError(_)
+            | HttpUtilError::JsonHeadersParseError(_)
+            | HttpUtilError::HeaderConvertError(_)
+            | HttpUtilError::InvalidHttpVersionError(_)
+            | HttpUtilError::RequestFromHyperError(_)
+            | HttpUtilError::RequestToHyperError(_)
+            | HttpUtilError::RequestCopyError(_) => ProxyError::InvalidRequestError(message),
+            HttpUtilError::DecodeError(_)
+            | HttpUtilError::EncodeError(_)
+            | HttpUtilError::ResponseFromHyperError(_)
+            | HttpUtilError::ResponseToHyperError(_)
+            | HttpUtilError::ResponseCopyError(_)
+            | HttpUtilError::ResponseDecodeError(_) => ProxyError::InvalidResponseError(message),
+            HttpUtilError::RequestSendToFrontError(_)
+            | HttpUtilError::ModifiedRequestReceiveError(_)
+            | HttpUtilError::ResponseSendToFrontError(_)
+            | HttpUtilError::ModifiedResponseReceiveError(_)
+            | HttpUtilError::InterceptWaitError(_)
+            | HttpUtilError::NoPendingExchangeError(_) => ProxyError::FrontendError(message),
+            HttpUtilError::CertificateGenerateError(_)
+            | HttpUtilError::CertificateSignError(_)
+            | HttpUtilError::IdentityBuildError(_)
+            | HttpUtilError::CertificateLoadError(_)
+            | HttpUtilError::CertificateSaveError(_)
+            | HttpUtilError::CertificateExportError(_) => ProxyError::TlsSetupError(message),
+        }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
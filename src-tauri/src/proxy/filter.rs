@@ -0,0 +1,340 @@
+use hyper::{header::CONTENT_TYPE, HeaderMap, Method, Uri};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::error::ProxyError;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMode {
+    Include,
+    Exclude,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Request,
+    Response,
+    Both,
+}
+
+// ** every condition that is set has to match, an unset one matches anything
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InterceptRule {
+    pub id: String,
+    pub enabled: bool,
+    pub mode: RuleMode,
+    pub direction: Direction,
+    // * glob, `*.example.com`
+    #[serde(default)]
+    pub host: Option<String>,
+    // * regex against the path without the query
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub method: Option<String>,
+    // * without the dot, `js`, `woff2`
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    // * header name that must be present on the message being held
+    #[serde(default)]
+    pub header: Option<String>,
+    // * substring of the content-type of the message being held
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+struct CompiledRule {
+    mode: RuleMode,
+    direction: Direction,
+    host: Option<Regex>,
+    path: Option<Regex>,
+    method: Option<Method>,
+    extensions: Vec<String>,
+    header: Option<String>,
+    content_type: Option<String>,
+}
+
+// ** the message being judged, a response is judged with the uri and method of its request
+pub struct Message<'a> {
+    pub direction: Direction,
+    pub uri: &'a Uri,
+    pub method: &'a Method,
+    pub headers: &'a HeaderMap,
+}
+
+// ** a message is held when it matches an include rule, or there is none for its direction,
+// ** and matches no exclude rule, without any enabled rule nothing is held
+pub struct InterceptFilter {
+    rules: Vec<CompiledRule>,
+}
+
+impl InterceptFilter {
+    pub fn new(rules: &[InterceptRule]) -> Result<Self, ProxyError> {
+        let mut compiled = Vec::new();
+        for rule in rules.iter().filter(|r| r.enabled) {
+            compiled.push(CompiledRule::new(rule)?);
+        }
+        Ok(InterceptFilter { rules: compiled })
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let mut has_include = false;
+        let mut included = false;
+        for rule in self
+            .rules
+            .iter()
+            .filter(|r| r.applies_to(message.direction))
+        {
+            match rule.mode {
+                RuleMode::Exclude => {
+                    if rule.matches(message) {
+                        return false;
+                    }
+                }
+                RuleMode::Include => {
+                    has_include = true;
+                    included = included || rule.matches(message);
+                }
+            }
+        }
+        !has_include || included
+    }
+}
+
+impl CompiledRule {
+    fn new(rule: &InterceptRule) -> Result<Self, ProxyError> {
+        let host = match &rule.host {
+            Some(h) => Some(glob_to_regex(h)?),
+            None => None,
+        };
+        let path = match &rule.path {
+            Some(p) => match Regex::new(p) {
+                Ok(r) => Some(r),
+                Err(e) => return Err(ProxyError::InvalidRuleError(e.to_string())),
+            },
+            None => None,
+        };
+        let method = match &rule.method {
+            Some(m) => match Method::from_bytes(m.to_ascii_uppercase().as_bytes()) {
+                Ok(m) => Some(m),
+                Err(e) => return Err(ProxyError::InvalidRuleError(e.to_string())),
+            },
+            None => None,
+        };
+        Ok(CompiledRule {
+            mode: rule.mode,
+            direction: rule.direction,
+            host,
+            path,
+            method,
+            extensions: rule
+                .extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            header: rule.header.clone(),
+            content_type: rule.content_type.as_ref().map(|c| c.to_ascii_lowercase()),
+        })
+    }
+
+    fn applies_to(&self, direction: Direction) -> bool {
+        self.direction == Direction::Both || self.direction == direction
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        if let Some(host) = &self.host {
+            if !host.is_match(message.uri.host().unwrap_or("")) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if !path.is_match(message.uri.path()) {
+                return false;
+            }
+        }
+        if let Some(method) = &self.method {
+            if method != message.method {
+                return false;
+            }
+        }
+        if !self.extensions.is_empty() {
+            let extension = path_extension(message.uri.path());
+            if !self
+                .extensions
+                .iter()
+                .any(|e| Some(e.as_str()) == extension.as_deref())
+            {
+                return false;
+            }
+        }
+        if let Some(header) = &self.header {
+            if !message.headers.contains_key(header.as_str()) {
+                return false;
+            }
+        }
+        if let Some(content_type) = &self.content_type {
+            let value = match message.headers.get(CONTENT_TYPE) {
+                Some(v) => String::from_utf8_lossy(v.as_bytes()).to_ascii_lowercase(),
+                None => return false,
+            };
+            if !value.contains(content_type.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn path_extension(path: &str) -> Option<String> {
+    let file = path.rsplit('/').next()?;
+    let (_, extension) = file.rsplit_once('.')?;
+    Some(extension.to_ascii_lowercase())
+}
+
+// ** `*` stands for any run of characters, matching is case insensitive and anchored
+pub fn glob_to_regex(glob: &str) -> Result<Regex, ProxyError> {
+    let pattern = glob
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<String>>()
+        .join(".*");
+    match Regex::new(&format!("(?i)^{}$", pattern)) {
+        Ok(r) => Ok(r),
+        Err(e) => Err(ProxyError::InvalidRuleError(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(mode: RuleMode, direction: Direction) -> InterceptRule {
+        InterceptRule {
+            id: "rule".to_string(),
+            enabled: true,
+            mode,
+            direction,
+            host: None,
+            path: None,
+            method: None,
+            extensions: Vec::new(),
+            header: None,
+            content_type: None,
+        }
+    }
+
+    fn matches(filter: &InterceptFilter, direction: Direction, url: &str) -> bool {
+        let uri = url.parse::<Uri>().unwrap();
+        filter.matches(&Message {
+            direction,
+            uri: &uri,
+            method: &Method::GET,
+            headers: &HeaderMap::new(),
+        })
+    }
+
+    #[test]
+    fn no_rules_hold_nothing() {
+        let filter = InterceptFilter::new(&[]).unwrap();
+        assert!(!matches(&filter, Direction::Request, "http://example.com/"));
+        let disabled = InterceptRule {
+            enabled: false,
+            ..rule(RuleMode::Include, Direction::Both)
+        };
+        let filter = InterceptFilter::new(&[disabled]).unwrap();
+        assert!(!matches(&filter, Direction::Request, "http://example.com/"));
+    }
+
+    #[test]
+    fn without_include_rules_everything_not_excluded_matches() {
+        let exclude = InterceptRule {
+            extensions: vec!["png".to_string()],
+            ..rule(RuleMode::Exclude, Direction::Both)
+        };
+        let filter = InterceptFilter::new(&[exclude]).unwrap();
+        assert!(matches(&filter, Direction::Request, "http://example.com/"));
+        assert!(matches(&filter, Direction::Response, "http://a.test/x.js"));
+        assert!(!matches(
+            &filter,
+            Direction::Request,
+            "http://example.com/logo.PNG"
+        ));
+    }
+
+    #[test]
+    fn exclude_beats_include() {
+        let include = InterceptRule {
+            host: Some("*.example.com".to_string()),
+            ..rule(RuleMode::Include, Direction::Both)
+        };
+        let exclude = InterceptRule {
+            path: Some("^/static/".to_string()),
+            ..rule(RuleMode::Exclude, Direction::Both)
+        };
+        let filter = InterceptFilter::new(&[include, exclude]).unwrap();
+        assert!(matches(
+            &filter,
+            Direction::Request,
+            "http://api.example.com/users"
+        ));
+        assert!(!matches(
+            &filter,
+            Direction::Request,
+            "http://api.example.com/static/a.css"
+        ));
+        assert!(!matches(
+            &filter,
+            Direction::Request,
+            "http://other.com/users"
+        ));
+    }
+
+    #[test]
+    fn rules_only_judge_their_direction() {
+        let include = InterceptRule {
+            host: Some("api.example.com".to_string()),
+            ..rule(RuleMode::Include, Direction::Request)
+        };
+        let exclude = InterceptRule {
+            host: Some("cdn.example.com".to_string()),
+            ..rule(RuleMode::Exclude, Direction::Response)
+        };
+        let filter = InterceptFilter::new(&[include, exclude]).unwrap();
+        assert!(matches(
+            &filter,
+            Direction::Request,
+            "http://api.example.com/"
+        ));
+        assert!(!matches(
+            &filter,
+            Direction::Request,
+            "http://cdn.example.com/"
+        ));
+        // * no include rule for responses, only the exclude one applies
+        assert!(matches(&filter, Direction::Response, "http://other.com/"));
+        assert!(!matches(
+            &filter,
+            Direction::Response,
+            "http://cdn.example.com/"
+        ));
+    }
+
+    #[test]
+    fn glob_only_treats_star_as_special() {
+        let glob = glob_to_regex("*.example.com").unwrap();
+        assert!(glob.is_match("api.example.com"));
+        assert!(glob.is_match("API.Example.COM"));
+        assert!(!glob.is_match("example.com"));
+        assert!(!glob.is_match("api.examplexcom"));
+        assert!(!glob.is_match("api.example.com.evil.net"));
+        let glob = glob_to_regex("a?b").unwrap();
+        assert!(glob.is_match("a?b"));
+        assert!(!glob.is_match("axb"));
+        assert!(!glob.is_match("ab"));
+    }
+}
@@ -0,0 +1,332 @@
+use hyper::{server::conn::AddrStream, Server};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tauri::{AppHandle, Manager};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+use super::{
+    error::ProxyError,
+    handle,
+    reverse::ReverseRouter,
+    socks::{self, SocksCredentials},
+    transparent, tunnel, ClientAddr, ProxyContext,
+};
+use crate::config::{ListenerConfig, ListenerKind};
+
+type ServerFuture = Pin<Box<dyn Future<Output = Result<(), ProxyError>> + Send>>;
+
+const LISTENER_STATUS_EVENT: &str = "listener-status";
+
+#[derive(Serialize, Clone)]
+pub struct ListenerStatus {
+    pub id: String,
+    pub running: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ListenerInfo {
+    #[serde(flatten)]
+    pub config: ListenerConfig,
+    pub running: bool,
+}
+
+// * the task has released the socket once it finished
+struct RunningListener {
+    shutdown_sender: oneshot::Sender<()>,
+    // * yields the error the server stopped with
+    task: JoinHandle<Option<String>>,
+    app_handle: AppHandle,
+    // * tells a restarted listener apart from the one it replaced under the same id
+    instance: u64,
+}
+
+// ** running listeners keyed by listener id, dropping the sender shuts the server down
+pub struct ListenerManager {
+    running: Mutex<HashMap<String, RunningListener>>,
+    next_instance: AtomicU64,
+}
+
+impl ListenerManager {
+    pub fn new() -> Self {
+        ListenerManager {
+            running: Mutex::new(HashMap::new()),
+            next_instance: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.running.lock().unwrap().contains_key(id)
+    }
+
+    pub fn list(&self, context: &ProxyContext) -> Vec<ListenerInfo> {
+        let config = context.config.read().unwrap();
+        config
+            .listeners
+            .iter()
+            .map(|l| ListenerInfo {
+                config: l.clone(),
+                running: self.is_running(&l.id),
+            })
+            .collect()
+    }
+
+    pub fn start_enabled(&self, context: &ProxyContext, app_handle: &AppHandle) {
+        let listeners = context.config.read().unwrap().listeners.clone();
+        for listener in listeners.iter().filter(|l| l.enabled) {
+            if let Err(e) = self.start(listener, context.clone(), app_handle.clone()) {
+                println!("proxy error{}", e);
+            }
+        }
+    }
+
+    pub fn start(
+        &self,
+        listener: &ListenerConfig,
+        context: ProxyContext,
+        app_handle: AppHandle,
+    ) -> Result<(), ProxyError> {
+        if self.is_running(&listener.id) {
+            return Ok(());
+        }
+        let id = listener.id.clone();
+        let addr = match listener.socket_addr() {
+            Ok(a) => a,
+            Err(e) => {
+                let e = ProxyError::BindError(e.to_string());
+                emit_status(&app_handle, &id, false, Some(e.to_string()));
+                return Err(e);
+            }
+        };
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+        let server = match &listener.kind {
+            ListenerKind::Http => {
+                http_server(addr, None, context, app_handle.clone(), shutdown_receiver)
+            }
+            ListenerKind::Socks5 { username, password } => {
+                let credentials = username.as_ref().map(|u| SocksCredentials {
+                    username: u.clone(),
+                    password: password.clone().unwrap_or_default(),
+                });
+                socks_server(
+                    addr,
+                    credentials,
+                    context,
+                    app_handle.clone(),
+                    shutdown_receiver,
+                )
+            }
+            ListenerKind::Transparent => {
+                transparent_server(addr, context, app_handle.clone(), shutdown_receiver)
+            }
+            ListenerKind::Reverse(reverse) => match ReverseRouter::new(reverse) {
+                Ok(router) => http_server(
+                    addr,
+                    Some(Arc::new(router)),
+                    context,
+                    app_handle.clone(),
+                    shutdown_receiver,
+                ),
+                Err(e) => Err(e),
+            },
+        };
+        let server = match server {
+            Ok(s) => s,
+            Err(e) => {
+                emit_status(&app_handle, &id, false, Some(e.to_string()));
+                return Err(e);
+            }
+        };
+        emit_status(&app_handle, &id, true, None);
+
+        let instance = self.next_instance.fetch_add(1, Ordering::Relaxed);
+        // * held until the entry is in, a server failing right away still finds itself
+        let mut running = self.running.lock().unwrap();
+        let task_id = id.clone();
+        let task_app_handle = app_handle.clone();
+        let task = tokio::spawn(async move {
+            let app_handle = task_app_handle;
+            let error = match server.await {
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            };
+            // * a stopped listener is no longer here, `stop` reports it once the task is done
+            let manager = app_handle.state::<ListenerManager>();
+            let mut running = manager.running.lock().unwrap();
+            match running.get(&task_id) {
+                Some(r) if r.instance == instance => {}
+                _ => return error,
+            }
+            running.remove(&task_id);
+            drop(running);
+            emit_status(&app_handle, &task_id, false, error);
+            None
+        });
+        running.insert(
+            id,
+            RunningListener {
+                shutdown_sender,
+                task,
+                app_handle,
+                instance,
+            },
+        );
+        Ok(())
+    }
+
+    pub async fn stop_all(&self) {
+        let stopped = self.running.lock().unwrap().drain().collect::<Vec<_>>();
+        for (id, listener) in stopped {
+            Self::shut_down(&id, listener).await;
+        }
+    }
+
+    pub async fn stop(&self, id: &str) {
+        let stopped = self.running.lock().unwrap().remove(id);
+        if let Some(listener) = stopped {
+            Self::shut_down(id, listener).await;
+        }
+    }
+
+    // ** returns once the socket is closed, so the same address can be bound again
+    async fn shut_down(id: &str, listener: RunningListener) {
+        let _ = listener.shutdown_sender.send(());
+        let error = match listener.task.await {
+            Ok(e) => e,
+            Err(e) => Some(e.to_string()),
+        };
+        emit_status(&listener.app_handle, id, false, error);
+    }
+}
+
+// ** with a router every request is origin-form and rewritten onto its upstream, CONNECT included
+fn http_server(
+    addr: SocketAddr,
+    reverse: Option<Arc<ReverseRouter>>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+    shutdown_receiver: oneshot::Receiver<()>,
+) -> Result<ServerFuture, ProxyError> {
+    let builder = match Server::try_bind(&addr) {
+        Ok(b) => b,
+        Err(e) => return Err(ProxyError::BindError(format!("{}: {}", addr, e))),
+    };
+
+    let service_app_handle = app_handle;
+    let make_service = hyper::service::make_service_fn(move |conn: &AddrStream| {
+        let app_handle = service_app_handle.clone();
+        let context = context.clone();
+        let reverse = reverse.clone();
+        let client_addr = ClientAddr(conn.remote_addr());
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                move |mut request: hyper::Request<hyper::Body>| {
+                    let app_handle = app_handle.clone();
+                    let context = context.clone();
+                    let reverse = reverse.clone();
+                    request.extensions_mut().insert(client_addr);
+                    async move {
+                        if let Some(reverse) = reverse {
+                            let request = match reverse.rewrite(request) {
+                                Ok(rq) => rq,
+                                Err(e) => {
+                                    let mut response =
+                                        hyper::Response::new(hyper::Body::from(e.to_string()));
+                                    *response.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                                    return Ok::<_, Infallible>(response);
+                                }
+                            };
+                            return Ok::<_, Infallible>(handle(request, context, app_handle).await);
+                        }
+                        if request.method() == hyper::Method::CONNECT {
+                            return Ok::<_, Infallible>(tunnel::handle_connect(
+                                request, context, app_handle,
+                            ));
+                        }
+                        Ok::<_, Infallible>(handle(request, context, app_handle).await)
+                    }
+                },
+            ))
+        }
+    });
+
+    // * not graceful, held exchanges would keep the port bound, open connections finish on their own
+    let server = builder.serve(make_service);
+    Ok(Box::pin(async move {
+        tokio::select! {
+            r = server => match r {
+                Ok(_) => Ok(()),
+                Err(e) => Err(ProxyError::ServeError(e.to_string())),
+            },
+            _ = shutdown_receiver => Ok(()),
+        }
+    }))
+}
+
+fn socks_server(
+    addr: SocketAddr,
+    credentials: Option<SocksCredentials>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+    shutdown_receiver: oneshot::Receiver<()>,
+) -> Result<ServerFuture, ProxyError> {
+    let listener = bind(addr)?;
+    Ok(Box::pin(socks::serve(
+        listener,
+        credentials,
+        context,
+        app_handle,
+        shutdown_receiver,
+    )))
+}
+
+fn transparent_server(
+    addr: SocketAddr,
+    context: ProxyContext,
+    app_handle: AppHandle,
+    shutdown_receiver: oneshot::Receiver<()>,
+) -> Result<ServerFuture, ProxyError> {
+    let listener = transparent::bind(addr)?;
+    Ok(Box::pin(transparent::serve(
+        listener,
+        context,
+        app_handle,
+        shutdown_receiver,
+    )))
+}
+
+// * bound here, so a taken port is reported before the listener counts as running
+fn bind(addr: SocketAddr) -> Result<TcpListener, ProxyError> {
+    let listener = match std::net::TcpListener::bind(addr) {
+        Ok(l) => l,
+        Err(e) => return Err(ProxyError::BindError(format!("{}: {}", addr, e))),
+    };
+    if let Err(e) = listener.set_nonblocking(true) {
+        return Err(ProxyError::BindError(e.to_string()));
+    }
+    match TcpListener::from_std(listener) {
+        Ok(l) => Ok(l),
+        Err(e) => Err(ProxyError::BindError(e.to_string())),
+    }
+}
+
+fn emit_status(app_handle: &AppHandle, id: &str, running: bool, error: Option<String>) {
+    let status = ListenerStatus {
+        id: id.to_string(),
+        running,
+        error,
+    };
+    if let Err(e) = app_handle.emit_all(LISTENER_STATUS_EVENT, status) {
+        println!("failed to emit listener status: {}", e);
+    }
+}
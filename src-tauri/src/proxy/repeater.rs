@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use super::{error::ProxyError, upstream::UpstreamClient};
+use crate::history::now_millis;
+use crate::http_util::{request::RequestForFront, response::ResponseForFront};
+
+// * the oldest attempts of a tab are dropped past this
+const TAB_HISTORY_LIMIT: usize = 100;
+
+#[derive(Serialize, Clone)]
+pub struct RepeaterTiming {
+    // * unix milliseconds
+    pub started_at: i64,
+    // * until the response head arrived
+    pub headers_ms: u64,
+    // * until the whole body was read
+    pub total_ms: u64,
+}
+
+// ** one attempt, a failed send is kept as well so it can be compared with the others
+#[derive(Serialize, Clone)]
+pub struct RepeaterEntry {
+    pub id: u64,
+    pub request: RequestForFront,
+    pub response: Option<ResponseForFront>,
+    pub error: Option<String>,
+    pub upstream_addr: Option<String>,
+    pub connection_reused: bool,
+    pub timing: RepeaterTiming,
+}
+
+#[derive(Default)]
+struct Tab {
+    next_id: u64,
+    entries: Vec<RepeaterEntry>,
+}
+
+// ** requests sent by hand, bypassing scope, rules and the pilot, grouped by frontend tab
+pub struct Repeater {
+    tabs: Mutex<HashMap<String, Tab>>,
+}
+
+impl Repeater {
+    pub fn new() -> Self {
+        Repeater {
+            tabs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // * a request that cannot be built is an error, not an attempt
+    pub async fn send(
+        &self,
+        upstream: &UpstreamClient,
+        tab: &str,
+        request: RequestForFront,
+    ) -> Result<RepeaterEntry, ProxyError> {
+        let rq = request.clone().to_hyper().await?;
+        let started_at = now_millis();
+        let start = Instant::now();
+
+        let (response, error, connection, headers_ms) = match upstream.send(rq).await {
+            Ok((rs, connection)) => {
+                let headers_ms = start.elapsed().as_millis() as u64;
+                match ResponseForFront::from_hyper(rs, None).await {
+                    Ok(rs) => (Some(rs), None, Some(connection), headers_ms),
+                    Err(e) => (
+                        None,
+                        Some(ProxyError::from(e).to_string()),
+                        Some(connection),
+                        headers_ms,
+                    ),
+                }
+            }
+            Err(e) => (
+                None,
+                Some(e.to_string()),
+                None,
+                start.elapsed().as_millis() as u64,
+            ),
+        };
+        let (upstream_addr, connection_reused) = match connection {
+            Some(c) => (c.remote_addr, c.reused),
+            None => (None, false),
+        };
+        let timing = RepeaterTiming {
+            started_at,
+            headers_ms,
+            total_ms: start.elapsed().as_millis() as u64,
+        };
+
+        let mut tabs = self.tabs.lock().unwrap();
+        let tab = tabs.entry(tab.to_string()).or_default();
+        let entry = RepeaterEntry {
+            id: tab.next_id,
+            request,
+            response,
+            error,
+            upstream_addr,
+            connection_reused,
+            timing,
+        };
+        tab.next_id += 1;
+        tab.entries.push(entry.clone());
+        if tab.entries.len() > TAB_HISTORY_LIMIT {
+            tab.entries.remove(0);
+        }
+        Ok(entry)
+    }
+
+    // * oldest first
+    pub fn history(&self, tab: &str) -> Vec<RepeaterEntry> {
+        match self.tabs.lock().unwrap().get(tab) {
+            Some(t) => t.entries.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn close(&self, tab: &str) {
+        self.tabs.lock().unwrap().remove(tab);
+    }
+}
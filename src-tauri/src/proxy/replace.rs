@@ -0,0 +1,383 @@
+use bytes::Bytes;
+use hyper::{
+    header::{HeaderName, HeaderValue, CONTENT_ENCODING},
+    Body, HeaderMap, Method, Request, Response, Uri,
+};
+use regex::{bytes::Regex as BytesRegex, NoExpand, Regex};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::error::ProxyError;
+use crate::http_util::{
+    encode::SupportedEncoding, header::fix_content_length, traits::VersionMethods,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaceTarget {
+    // * `METHOD uri VERSION`
+    RequestLine,
+    // * each header as one `name: value` line
+    RequestHeader,
+    RequestBody,
+    ResponseHeader,
+    ResponseBody,
+}
+
+// ** applied in list order, a header line replaced with nothing is removed and an empty
+// ** header pattern adds `replacement` as a new header line
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplaceRule {
+    pub id: String,
+    pub enabled: bool,
+    pub target: ReplaceTarget,
+    #[serde(default)]
+    pub regex: bool,
+    pub pattern: String,
+    pub replacement: String,
+}
+
+// * every context a zero-width assertion (`^`, `$`, `\b`, `\B`) can hold in
+const EMPTY_MATCH_PROBES: &[&str] = &["", "a", " ", "a a", "a\na", "\r\n"];
+
+struct CompiledReplaceRule {
+    target: ReplaceTarget,
+    literal: bool,
+    text: Regex,
+    bytes: BytesRegex,
+    pattern_empty: bool,
+    replacement: String,
+}
+
+pub struct Replacer {
+    rules: Vec<CompiledReplaceRule>,
+}
+
+impl Replacer {
+    pub fn new(rules: &[ReplaceRule]) -> Result<Self, ProxyError> {
+        let mut compiled = Vec::new();
+        for rule in rules.iter().filter(|r| r.enabled) {
+            compiled.push(CompiledReplaceRule::new(rule)?);
+        }
+        Ok(Replacer { rules: compiled })
+    }
+
+    pub async fn apply_request(&self, request: Request<Body>) -> Result<Request<Body>, ProxyError> {
+        let (mut parts, body) = request.into_parts();
+
+        let line_rules = self.rules_for(ReplaceTarget::RequestLine);
+        if !line_rules.is_empty() {
+            let version = parts.version.to_string()?;
+            let line = format!("{} {} {}", parts.method, parts.uri, version);
+            let replaced = replace_text(&line_rules, line.clone());
+            if replaced != line {
+                let (method, uri, version) = parse_request_line(&replaced)?;
+                parts.method = method;
+                parts.uri = uri;
+                parts.version = version;
+            }
+        }
+
+        replace_headers(
+            &self.rules_for(ReplaceTarget::RequestHeader),
+            &mut parts.headers,
+        )?;
+        let body = replace_body(
+            &self.rules_for(ReplaceTarget::RequestBody),
+            &mut parts.headers,
+            body,
+        )
+        .await?;
+        Ok(Request::from_parts(parts, body))
+    }
+
+    pub async fn apply_response(
+        &self,
+        response: Response<Body>,
+    ) -> Result<Response<Body>, ProxyError> {
+        let (mut parts, body) = response.into_parts();
+        replace_headers(
+            &self.rules_for(ReplaceTarget::ResponseHeader),
+            &mut parts.headers,
+        )?;
+        let body = replace_body(
+            &self.rules_for(ReplaceTarget::ResponseBody),
+            &mut parts.headers,
+            body,
+        )
+        .await?;
+        Ok(Response::from_parts(parts, body))
+    }
+
+    fn rules_for(&self, target: ReplaceTarget) -> Vec<&CompiledReplaceRule> {
+        self.rules.iter().filter(|r| r.target == target).collect()
+    }
+}
+
+impl CompiledReplaceRule {
+    fn new(rule: &ReplaceRule) -> Result<Self, ProxyError> {
+        let is_header = matches!(
+            rule.target,
+            ReplaceTarget::RequestHeader | ReplaceTarget::ResponseHeader
+        );
+        // * anywhere else it would put the replacement between every two characters
+        if rule.pattern.is_empty() && !is_header {
+            return Err(ProxyError::InvalidReplaceRuleError(format!(
+                "rule {} has an empty pattern",
+                rule.id
+            )));
+        }
+        let pattern = if rule.regex {
+            rule.pattern.clone()
+        } else {
+            regex::escape(&rule.pattern)
+        };
+        let text = match Regex::new(&pattern) {
+            Ok(r) => r,
+            Err(e) => return Err(ProxyError::InvalidReplaceRuleError(e.to_string())),
+        };
+        if !rule.pattern.is_empty() && matches_empty(&text) {
+            return Err(ProxyError::InvalidReplaceRuleError(format!(
+                "rule {}: {} can match the empty string",
+                rule.id, rule.pattern
+            )));
+        }
+        let bytes = match BytesRegex::new(&pattern) {
+            Ok(r) => r,
+            Err(e) => return Err(ProxyError::InvalidReplaceRuleError(e.to_string())),
+        };
+        Ok(CompiledReplaceRule {
+            target: rule.target,
+            literal: !rule.regex,
+            text,
+            bytes,
+            pattern_empty: rule.pattern.is_empty(),
+            replacement: rule.replacement.clone(),
+        })
+    }
+
+    fn replace_text(&self, text: &str) -> String {
+        if self.literal {
+            self.text
+                .replace_all(text, NoExpand(&self.replacement))
+                .into_owned()
+        } else {
+            self.text
+                .replace_all(text, self.replacement.as_str())
+                .into_owned()
+        }
+    }
+
+    fn replace_bytes(&self, b: &[u8]) -> Vec<u8> {
+        if self.literal {
+            self.bytes
+                .replace_all(b, regex::bytes::NoExpand(self.replacement.as_bytes()))
+                .into_owned()
+        } else {
+            self.bytes
+                .replace_all(b, self.replacement.as_bytes())
+                .into_owned()
+        }
+    }
+}
+
+fn replace_text(rules: &[&CompiledReplaceRule], text: String) -> String {
+    rules.iter().fold(text, |t, r| r.replace_text(&t))
+}
+
+fn matches_empty(regex: &Regex) -> bool {
+    EMPTY_MATCH_PROBES
+        .iter()
+        .any(|p| regex.find_iter(p).any(|m| m.is_empty()))
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, Uri, hyper::Version), ProxyError> {
+    let mut fields = line.split_whitespace();
+    let (method, uri, version) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(m), Some(u), Some(v)) => (m, u, v),
+        _ => {
+            return Err(ProxyError::ReplaceError(format!(
+                "`{}` is not a request line",
+                line
+            )))
+        }
+    };
+    let method = match Method::from_bytes(method.as_bytes()) {
+        Ok(m) => m,
+        Err(e) => return Err(ProxyError::ReplaceError(e.to_string())),
+    };
+    let uri = match uri.parse::<Uri>() {
+        Ok(u) => u,
+        Err(e) => return Err(ProxyError::ReplaceError(e.to_string())),
+    };
+    let version = hyper::Version::from_str(version)?;
+    Ok((method, uri, version))
+}
+
+// ** headers are only rebuilt when a rule changed something, so untouched binary values survive
+fn replace_headers(
+    rules: &[&CompiledReplaceRule],
+    headers: &mut HeaderMap,
+) -> Result<(), ProxyError> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let original: Vec<String> = headers
+        .iter()
+        .map(|(k, v)| format!("{}: {}", k, String::from_utf8_lossy(v.as_bytes())))
+        .collect();
+    let mut lines = original.clone();
+    for rule in rules {
+        if rule.pattern_empty {
+            lines.push(rule.replacement.clone());
+            continue;
+        }
+        lines = lines
+            .into_iter()
+            .map(|l| rule.replace_text(&l))
+            .filter(|l| !l.trim().is_empty())
+            .collect();
+    }
+    if lines == original {
+        return Ok(());
+    }
+
+    let mut replaced = HeaderMap::new();
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some(t) => t,
+            None => {
+                return Err(ProxyError::ReplaceError(format!(
+                    "`{}` is not a header line",
+                    line
+                )))
+            }
+        };
+        let name = match HeaderName::from_str(name.trim()) {
+            Ok(n) => n,
+            Err(e) => return Err(ProxyError::ReplaceError(e.to_string())),
+        };
+        let value = match HeaderValue::from_str(value.trim_start()) {
+            Ok(v) => v,
+            Err(e) => return Err(ProxyError::ReplaceError(e.to_string())),
+        };
+        replaced.append(name, value);
+    }
+    *headers = replaced;
+    Ok(())
+}
+
+// ** matching runs on the decoded body, which is encoded again as the headers declare
+async fn replace_body(
+    rules: &[&CompiledReplaceRule],
+    headers: &mut HeaderMap,
+    body: Body,
+) -> Result<Body, ProxyError> {
+    if rules.is_empty() {
+        return Ok(body);
+    }
+    let encodings = SupportedEncoding::from(headers.get(CONTENT_ENCODING))?;
+    let raw = match hyper::body::to_bytes(body).await {
+        Ok(b) => b,
+        Err(e) => return Err(ProxyError::ReplaceError(e.to_string())),
+    };
+    let decoded = SupportedEncoding::decode_all(&encodings, raw.clone())?;
+    let replaced = rules
+        .iter()
+        .fold(decoded.to_vec(), |b, r| r.replace_bytes(&b));
+    if replaced == decoded {
+        return Ok(Body::from(raw));
+    }
+    let encoded = SupportedEncoding::encode_all(&encodings, Bytes::from(replaced))?;
+    fix_content_length(headers, encoded.len());
+    Ok(Body::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{CONTENT_LENGTH, USER_AGENT};
+
+    fn rule(target: ReplaceTarget, regex: bool, pattern: &str, replacement: &str) -> ReplaceRule {
+        ReplaceRule {
+            id: "rule".to_string(),
+            enabled: true,
+            target,
+            regex,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn empty_pattern_is_refused_outside_headers() {
+        for target in [
+            ReplaceTarget::RequestLine,
+            ReplaceTarget::RequestBody,
+            ReplaceTarget::ResponseBody,
+        ] {
+            assert!(Replacer::new(&[rule(target, false, "", "x")]).is_err());
+        }
+        for target in [ReplaceTarget::RequestHeader, ReplaceTarget::ResponseHeader] {
+            assert!(Replacer::new(&[rule(target, false, "", "X-Added: 1")]).is_ok());
+        }
+    }
+
+    #[test]
+    fn regex_matching_the_empty_string_is_refused() {
+        for pattern in ["a*", "x?", "^", "\\b", "(?m)$"] {
+            let rules = [rule(ReplaceTarget::ResponseBody, true, pattern, "x")];
+            assert!(Replacer::new(&rules).is_err(), "{} was accepted", pattern);
+        }
+        let rules = [rule(ReplaceTarget::ResponseBody, true, "a+", "x")];
+        assert!(Replacer::new(&rules).is_ok());
+    }
+
+    #[tokio::test]
+    async fn header_replaced_with_nothing_is_removed() {
+        let replacer = Replacer::new(&[
+            rule(ReplaceTarget::RequestHeader, true, "^user-agent: .*$", ""),
+            rule(ReplaceTarget::RequestHeader, false, "", "x-added: 1"),
+        ])
+        .unwrap();
+        let request = Request::builder()
+            .uri("http://example.com/")
+            .header(USER_AGENT, "curl/8.0")
+            .header("accept", "*/*")
+            .body(Body::empty())
+            .unwrap();
+        let request = replacer.apply_request(request).await.unwrap();
+        assert!(!request.headers().contains_key(USER_AGENT));
+        assert_eq!(request.headers()["accept"], "*/*");
+        assert_eq!(request.headers()["x-added"], "1");
+    }
+
+    #[tokio::test]
+    async fn encoded_body_is_replaced_and_its_length_fixed() {
+        let encoded = SupportedEncoding::Gzip
+            .encode(Bytes::from_static(b"hello world"))
+            .unwrap();
+        let response = Response::builder()
+            .header(CONTENT_ENCODING, "gzip")
+            .header(CONTENT_LENGTH, encoded.len())
+            .body(Body::from(encoded))
+            .unwrap();
+        let replacer = Replacer::new(&[rule(
+            ReplaceTarget::ResponseBody,
+            false,
+            "world",
+            "everyone out there",
+        )])
+        .unwrap();
+        let response = replacer.apply_response(response).await.unwrap();
+        let length: usize = response.headers()[CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(length, body.len());
+        let decoded = SupportedEncoding::Gzip.decode(body).unwrap();
+        assert_eq!(decoded, Bytes::from_static(b"hello everyone out there"));
+    }
+}
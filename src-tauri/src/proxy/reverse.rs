@@ -0,0 +1,278 @@
+use hyper::{
+    header::{HeaderValue, HOST},
+    http::uri::{Authority, PathAndQuery, Scheme},
+    Body, Request, Uri,
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use super::error::ProxyError;
+
+const HTTP_DEFAULT_PORT: u16 = 80;
+const HTTPS_DEFAULT_PORT: u16 = 443;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReverseConfig {
+    // * base url for every path no route claims, `http://127.0.0.1:3000`
+    pub upstream: String,
+    // * off, the upstream sees the Host the client sent
+    #[serde(default = "default_rewrite_host")]
+    pub rewrite_host: bool,
+    #[serde(default)]
+    pub routes: Vec<ReverseRoute>,
+}
+
+fn default_rewrite_host() -> bool {
+    true
+}
+
+// ** requests under `prefix` go to `upstream` instead, the longest matching prefix wins
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReverseRoute {
+    // * `/api` takes `/api` and `/api/...`, not `/apis`
+    pub prefix: String,
+    pub upstream: String,
+    // * `/api/users` reaches the upstream as `/users`
+    #[serde(default)]
+    pub strip_prefix: bool,
+}
+
+struct Target {
+    scheme: Scheme,
+    authority: Authority,
+    // * without the trailing slash, empty for the root
+    base_path: String,
+}
+
+// ** turns the origin-form requests a reverse listener receives into absolute-form ones
+pub struct ReverseRouter {
+    default: Target,
+    routes: Vec<(String, bool, Target)>,
+    rewrite_host: bool,
+}
+
+impl ReverseRouter {
+    pub fn new(config: &ReverseConfig) -> Result<Self, ProxyError> {
+        let mut routes = Vec::new();
+        for route in &config.routes {
+            if !route.prefix.starts_with('/') {
+                return Err(ProxyError::InvalidReverseRouteError(format!(
+                    "prefix {} does not start with /",
+                    route.prefix
+                )));
+            }
+            let prefix = route.prefix.trim_end_matches('/').to_string();
+            routes.push((prefix, route.strip_prefix, parse_target(&route.upstream)?));
+        }
+        routes.sort_by_key(|r| Reverse(r.0.len()));
+        Ok(ReverseRouter {
+            default: parse_target(&config.upstream)?,
+            routes,
+            rewrite_host: config.rewrite_host,
+        })
+    }
+
+    pub fn rewrite(&self, request: Request<Body>) -> Result<Request<Body>, ProxyError> {
+        let (mut parts, body) = request.into_parts();
+        let path = parts.uri.path().to_string();
+        let (target, rest) = match self
+            .routes
+            .iter()
+            .find(|(prefix, _, _)| matches_prefix(&path, prefix))
+        {
+            Some((prefix, true, target)) => (target, &path[prefix.len()..]),
+            Some((_, false, target)) => (target, path.as_str()),
+            None => (&self.default, path.as_str()),
+        };
+        let rest = rest.trim_start_matches('/');
+        let mut path_and_query = format!("{}/{}", target.base_path, rest);
+        if let Some(query) = parts.uri.query() {
+            path_and_query.push('?');
+            path_and_query.push_str(query);
+        }
+        let path_and_query = match path_and_query.parse::<PathAndQuery>() {
+            Ok(pq) => pq,
+            Err(e) => return Err(ProxyError::InvalidReverseRouteError(e.to_string())),
+        };
+        parts.uri = match Uri::builder()
+            .scheme(target.scheme.clone())
+            .authority(target.authority.clone())
+            .path_and_query(path_and_query)
+            .build()
+        {
+            Ok(u) => u,
+            Err(e) => return Err(ProxyError::InvalidReverseRouteError(e.to_string())),
+        };
+        if self.rewrite_host {
+            let host = host_header(&target.scheme, &target.authority);
+            match HeaderValue::from_str(&host) {
+                Ok(v) => parts.headers.insert(HOST, v),
+                Err(e) => return Err(ProxyError::InvalidReverseRouteError(e.to_string())),
+            };
+        }
+        Ok(Request::from_parts(parts, body))
+    }
+}
+
+fn parse_target(url: &str) -> Result<Target, ProxyError> {
+    let uri = match url.parse::<Uri>() {
+        Ok(u) => u,
+        Err(e) => {
+            return Err(ProxyError::InvalidReverseRouteError(format!(
+                "{}: {}",
+                url, e
+            )))
+        }
+    };
+    let scheme = match uri.scheme() {
+        Some(s) if *s == Scheme::HTTP || *s == Scheme::HTTPS => s.clone(),
+        _ => {
+            return Err(ProxyError::InvalidReverseRouteError(format!(
+                "{}: upstream must be an http or https url",
+                url
+            )))
+        }
+    };
+    let authority = match uri.authority() {
+        Some(a) => a.clone(),
+        None => {
+            return Err(ProxyError::InvalidReverseRouteError(format!(
+                "{}: upstream has no host",
+                url
+            )))
+        }
+    };
+    if uri.query().is_some() {
+        return Err(ProxyError::InvalidReverseRouteError(format!(
+            "{}: upstream cannot have a query",
+            url
+        )));
+    }
+    Ok(Target {
+        scheme,
+        authority,
+        base_path: uri.path().trim_end_matches('/').to_string(),
+    })
+}
+
+fn matches_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+// * the default port is left out, as a browser would
+fn host_header(scheme: &Scheme, authority: &Authority) -> String {
+    let default_port = match *scheme == Scheme::HTTPS {
+        true => HTTPS_DEFAULT_PORT,
+        false => HTTP_DEFAULT_PORT,
+    };
+    match authority.port_u16() {
+        Some(p) if p == default_port => authority.host().to_string(),
+        _ => authority.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(prefix: &str, upstream: &str, strip_prefix: bool) -> ReverseRoute {
+        ReverseRoute {
+            prefix: prefix.to_string(),
+            upstream: upstream.to_string(),
+            strip_prefix,
+        }
+    }
+
+    fn router(rewrite_host: bool) -> ReverseRouter {
+        ReverseRouter::new(&ReverseConfig {
+            upstream: "http://127.0.0.1:3000/".to_string(),
+            rewrite_host,
+            routes: vec![
+                route("/api", "http://api.internal", false),
+                route("/api/v2/", "https://v2.internal:8443/base/", true),
+                route("/static", "https://cdn.internal:443", true),
+            ],
+        })
+        .unwrap()
+    }
+
+    // * absolute uri and Host the upstream sees
+    fn rewrite(router: &ReverseRouter, path: &str) -> (String, String) {
+        let request = Request::get(path)
+            .header(HOST, "proxy.local:8080")
+            .body(Body::empty())
+            .unwrap();
+        let request = router.rewrite(request).unwrap();
+        let host = request.headers()[HOST].to_str().unwrap().to_string();
+        (request.uri().to_string(), host)
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let router = router(true);
+        assert_eq!(
+            rewrite(&router, "/api/v2/users?page=2"),
+            (
+                "https://v2.internal:8443/base/users?page=2".to_string(),
+                "v2.internal:8443".to_string()
+            )
+        );
+        assert_eq!(
+            rewrite(&router, "/api/v1/users"),
+            (
+                "http://api.internal/api/v1/users".to_string(),
+                "api.internal".to_string()
+            )
+        );
+        assert_eq!(
+            rewrite(&router, "/api/v2").0,
+            "https://v2.internal:8443/base/"
+        );
+    }
+
+    #[test]
+    fn a_prefix_only_takes_whole_segments() {
+        let router = router(true);
+        assert_eq!(rewrite(&router, "/apis").0, "http://127.0.0.1:3000/apis");
+        assert_eq!(
+            rewrite(&router, "/static/app.js").0,
+            "https://cdn.internal:443/app.js"
+        );
+        assert_eq!(
+            rewrite(&router, "/staticky").0,
+            "http://127.0.0.1:3000/staticky"
+        );
+    }
+
+    #[test]
+    fn rewrite_host_leaves_out_the_default_port() {
+        let router = router(true);
+        assert_eq!(rewrite(&router, "/static/").1, "cdn.internal");
+        assert_eq!(rewrite(&router, "/").1, "127.0.0.1:3000");
+    }
+
+    #[test]
+    fn without_rewrite_host_the_client_host_is_kept() {
+        let router = router(false);
+        assert_eq!(
+            rewrite(&router, "/api/v2/users"),
+            (
+                "https://v2.internal:8443/base/users".to_string(),
+                "proxy.local:8080".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn a_prefix_must_be_a_path() {
+        let config = ReverseConfig {
+            upstream: "http://127.0.0.1:3000".to_string(),
+            rewrite_host: true,
+            routes: vec![route("api", "http://api.internal", false)],
+        };
+        assert!(ReverseRouter::new(&config).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use hyper::Uri;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::error::ProxyError;
+use super::filter::glob_to_regex;
+
+// ** what happens to an exchange outside the scope
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfScope {
+    // * shown in the history but never held
+    Record,
+    // * forwarded without telling the frontend
+    #[default]
+    Pass,
+    // * refused before it leaves the proxy
+    Block,
+}
+
+// ** every condition that is set has to match, empty lists match anything
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScopeRule {
+    // * glob, `*.example.com`
+    pub host: String,
+    #[serde(default)]
+    pub schemes: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ScopeConfig {
+    #[serde(default)]
+    pub include: Vec<ScopeRule>,
+    #[serde(default)]
+    pub exclude: Vec<ScopeRule>,
+    #[serde(default)]
+    pub out_of_scope: OutOfScope,
+}
+
+struct CompiledScopeRule {
+    host: Regex,
+    schemes: Vec<String>,
+    ports: Vec<u16>,
+    path_prefix: Option<String>,
+}
+
+// ** with no include rule everything is in scope, an exclude rule always wins
+pub struct Scope {
+    include: Vec<CompiledScopeRule>,
+    exclude: Vec<CompiledScopeRule>,
+    pub out_of_scope: OutOfScope,
+}
+
+impl Scope {
+    pub fn new(config: &ScopeConfig) -> Result<Self, ProxyError> {
+        let mut include = Vec::new();
+        for rule in &config.include {
+            include.push(CompiledScopeRule::new(rule)?);
+        }
+        let mut exclude = Vec::new();
+        for rule in &config.exclude {
+            exclude.push(CompiledScopeRule::new(rule)?);
+        }
+        Ok(Scope {
+            include,
+            exclude,
+            out_of_scope: config.out_of_scope,
+        })
+    }
+
+    pub fn contains(&self, uri: &Uri) -> bool {
+        if self.exclude.iter().any(|r| r.matches(uri)) {
+            return false;
+        }
+        self.include.is_empty() || self.include.iter().any(|r| r.matches(uri))
+    }
+}
+
+impl CompiledScopeRule {
+    fn new(rule: &ScopeRule) -> Result<Self, ProxyError> {
+        let host = match glob_to_regex(&rule.host) {
+            Ok(h) => h,
+            Err(e) => return Err(ProxyError::InvalidScopeError(e.to_string())),
+        };
+        Ok(CompiledScopeRule {
+            host,
+            schemes: rule
+                .schemes
+                .iter()
+                .map(|s| s.to_ascii_lowercase())
+                .collect(),
+            ports: rule.ports.clone(),
+            path_prefix: rule.path_prefix.clone(),
+        })
+    }
+
+    fn matches(&self, uri: &Uri) -> bool {
+        if !self.host.is_match(uri.host().unwrap_or("")) {
+            return false;
+        }
+        let scheme = uri.scheme_str().unwrap_or("http").to_ascii_lowercase();
+        if !self.schemes.is_empty() && !self.schemes.contains(&scheme) {
+            return false;
+        }
+        if !self.ports.is_empty() {
+            let port = match uri.port_u16() {
+                Some(p) => p,
+                None if scheme == "https" => 443,
+                None => 80,
+            };
+            if !self.ports.contains(&port) {
+                return false;
+            }
+        }
+        match &self.path_prefix {
+            Some(prefix) => uri.path().starts_with(prefix.as_str()),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(host: &str) -> ScopeRule {
+        ScopeRule {
+            host: host.to_string(),
+            schemes: Vec::new(),
+            ports: Vec::new(),
+            path_prefix: None,
+        }
+    }
+
+    fn scope(include: Vec<ScopeRule>, exclude: Vec<ScopeRule>) -> Scope {
+        Scope::new(&ScopeConfig {
+            include,
+            exclude,
+            out_of_scope: OutOfScope::Pass,
+        })
+        .unwrap()
+    }
+
+    fn contains(scope: &Scope, uri: &str) -> bool {
+        scope.contains(&uri.parse().unwrap())
+    }
+
+    #[test]
+    fn no_include_rule_keeps_everything_in_scope() {
+        let scope = scope(Vec::new(), Vec::new());
+        assert!(contains(&scope, "http://example.com/"));
+        assert!(contains(&scope, "/relative"));
+    }
+
+    #[test]
+    fn exclude_beats_include() {
+        let scope = scope(vec![rule("*.example.com")], vec![rule("cdn.example.com")]);
+        assert!(contains(&scope, "https://api.example.com/"));
+        assert!(contains(&scope, "http://API.Example.com/"));
+        assert!(!contains(&scope, "https://cdn.example.com/"));
+        assert!(!contains(&scope, "https://example.org/"));
+        assert!(!contains(&scope, "https://api.example.com.evil.org/"));
+        assert!(!contains(&scope, "/relative"));
+    }
+
+    #[test]
+    fn every_condition_of_a_rule_has_to_match() {
+        let scope = scope(
+            vec![ScopeRule {
+                schemes: vec!["HTTPS".to_string()],
+                ports: vec![443, 8443],
+                path_prefix: Some("/api/".to_string()),
+                ..rule("example.com")
+            }],
+            Vec::new(),
+        );
+        assert!(contains(&scope, "https://example.com/api/users"));
+        assert!(contains(&scope, "https://example.com:8443/api/"));
+        assert!(!contains(&scope, "http://example.com/api/users"));
+        assert!(!contains(&scope, "https://example.com:9443/api/"));
+        assert!(!contains(&scope, "https://example.com/apis"));
+    }
+
+    #[test]
+    fn the_default_port_follows_the_scheme() {
+        let scope = scope(
+            vec![ScopeRule {
+                ports: vec![80],
+                ..rule("*")
+            }],
+            Vec::new(),
+        );
+        assert!(contains(&scope, "http://example.com/"));
+        assert!(!contains(&scope, "https://example.com/"));
+        assert!(contains(&scope, "https://example.com:80/"));
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tauri::AppHandle;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+};
+
+use super::{error::ProxyError, tunnel, ProxyContext};
+
+pub const SOCKS_VERSION: u8 = 0x05;
+pub const SOCKS_AUTH_VERSION: u8 = 0x01;
+pub const SOCKS_NO_AUTH: u8 = 0x00;
+pub const SOCKS_USER_PASS: u8 = 0x02;
+pub const SOCKS_NO_ACCEPTABLE: u8 = 0xff;
+pub const SOCKS_CMD_CONNECT: u8 = 0x01;
+pub const SOCKS_ATYP_IPV4: u8 = 0x01;
+pub const SOCKS_ATYP_DOMAIN: u8 = 0x03;
+pub const SOCKS_ATYP_IPV6: u8 = 0x04;
+const SOCKS_REPLY_SUCCEEDED: u8 = 0x00;
+const SOCKS_REPLY_GENERAL_FAILURE: u8 = 0x01;
+const SOCKS_REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
+const SOCKS_REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS_REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const SOCKS_REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS_REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+pub fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "ttl expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+// ** `None` lets every client in, otherwise the username/password method is required
+#[derive(Clone)]
+pub struct SocksCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+// ** accepts until `shutdown` fires, every connection is served on its own task
+pub async fn serve(
+    listener: TcpListener,
+    credentials: Option<SocksCredentials>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+    mut shutdown: oneshot::Receiver<()>,
+) -> Result<(), ProxyError> {
+    loop {
+        let (stream, client_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(a) => a,
+                Err(e) => return Err(ProxyError::ServeError(e.to_string())),
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        let credentials = credentials.clone();
+        let context = context.clone();
+        let app_handle = app_handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                serve_connection(stream, client_addr, credentials, context, app_handle).await
+            {
+                println!("proxy error{}", e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    client_addr: SocketAddr,
+    credentials: Option<SocksCredentials>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError> {
+    negotiate_auth(&mut stream, credentials.as_ref()).await?;
+    let (host, port) = read_connect_request(&mut stream).await?;
+    // * the exchanges open their own upstream connections, this one only proves the destination answers
+    match context.upstream().connect(&host, port).await {
+        Ok(_) => reply(&mut stream, SOCKS_REPLY_SUCCEEDED).await?,
+        Err(e) => {
+            let code = reply_code(&e);
+            reply(&mut stream, code).await?;
+            return Err(ProxyError::SocksHandshakeError(format!(
+                "{}:{}: {}: {}",
+                host,
+                port,
+                reply_message(code),
+                e
+            )));
+        }
+    }
+
+    tunnel::serve_sniffed(stream, &host, port, client_addr, context, app_handle).await
+}
+
+async fn negotiate_auth(
+    stream: &mut TcpStream,
+    credentials: Option<&SocksCredentials>,
+) -> Result<(), ProxyError> {
+    let greeting = read(stream, 2).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(ProxyError::SocksHandshakeError(format!(
+            "unsupported socks version {}",
+            greeting[0]
+        )));
+    }
+    let methods = read(stream, greeting[1] as usize).await?;
+    let method = match credentials {
+        Some(_) => SOCKS_USER_PASS,
+        None => SOCKS_NO_AUTH,
+    };
+    if !methods.contains(&method) {
+        write(stream, &[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE]).await?;
+        return Err(ProxyError::SocksHandshakeError(
+            "client offered no acceptable auth method".to_string(),
+        ));
+    }
+    write(stream, &[SOCKS_VERSION, method]).await?;
+
+    let credentials = match credentials {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    // * RFC 1929
+    let version = read(stream, 2).await?;
+    if version[0] != SOCKS_AUTH_VERSION {
+        return Err(ProxyError::SocksHandshakeError(format!(
+            "unsupported auth version {}",
+            version[0]
+        )));
+    }
+    let username = read(stream, version[1] as usize).await?;
+    let password_len = read(stream, 1).await?[0] as usize;
+    let password = read(stream, password_len).await?;
+    if username != credentials.username.as_bytes() || password != credentials.password.as_bytes() {
+        write(stream, &[SOCKS_AUTH_VERSION, 1]).await?;
+        return Err(ProxyError::SocksHandshakeError(
+            "wrong username or password".to_string(),
+        ));
+    }
+    write(stream, &[SOCKS_AUTH_VERSION, 0]).await
+}
+
+async fn read_connect_request(stream: &mut TcpStream) -> Result<(String, u16), ProxyError> {
+    let request = read(stream, 4).await?;
+    let host = match request[3] {
+        SOCKS_ATYP_IPV4 => {
+            let octets = read(stream, 4).await?;
+            Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).to_string()
+        }
+        SOCKS_ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&read(stream, 16).await?);
+            Ipv6Addr::from(octets).to_string()
+        }
+        SOCKS_ATYP_DOMAIN => {
+            let len = read(stream, 1).await?[0] as usize;
+            String::from_utf8_lossy(&read(stream, len).await?).to_string()
+        }
+        t => {
+            reply(stream, SOCKS_REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(ProxyError::SocksHandshakeError(format!(
+                "unknown address type {}",
+                t
+            )));
+        }
+    };
+    let port = read(stream, 2).await?;
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    // * only CONNECT, neither BIND nor UDP ASSOCIATE carry http
+    if request[1] != SOCKS_CMD_CONNECT {
+        reply(stream, SOCKS_REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(ProxyError::SocksHandshakeError(format!(
+            "{}: {}",
+            reply_message(SOCKS_REPLY_COMMAND_NOT_SUPPORTED),
+            request[1]
+        )));
+    }
+    Ok((host, port))
+}
+
+// * RFC 1928 has no code for a timeout, the host did not answer
+fn reply_code(e: &io::Error) -> u8 {
+    match e.kind() {
+        io::ErrorKind::NetworkUnreachable => SOCKS_REPLY_NETWORK_UNREACHABLE,
+        io::ErrorKind::HostUnreachable | io::ErrorKind::TimedOut => SOCKS_REPLY_HOST_UNREACHABLE,
+        io::ErrorKind::ConnectionRefused => SOCKS_REPLY_CONNECTION_REFUSED,
+        _ => SOCKS_REPLY_GENERAL_FAILURE,
+    }
+}
+
+// * the bound address is not meaningful for us and is always sent as 0.0.0.0:0
+async fn reply(stream: &mut TcpStream, reply: u8) -> Result<(), ProxyError> {
+    write(
+        stream,
+        &[SOCKS_VERSION, reply, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+    )
+    .await
+}
+
+async fn write(stream: &mut TcpStream, bytes: &[u8]) -> Result<(), ProxyError> {
+    match stream.write_all(bytes).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ProxyError::SocksHandshakeError(e.to_string())),
+    }
+}
+
+async fn read(stream: &mut TcpStream, len: usize) -> Result<Vec<u8>, ProxyError> {
+    let mut buf = vec![0; len];
+    match stream.read_exact(&mut buf).await {
+        Ok(_) => Ok(buf),
+        Err(e) => Err(ProxyError::SocksHandshakeError(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::upstream::{UpstreamClient, UpstreamConfig};
+
+    async fn reply_for(host: &str, port: u16) -> u8 {
+        let upstream = UpstreamClient::new(&UpstreamConfig::default()).unwrap();
+        match upstream.connect(host, port).await {
+            Ok(_) => SOCKS_REPLY_SUCCEEDED,
+            Err(e) => reply_code(&e),
+        }
+    }
+
+    #[tokio::test]
+    async fn success_only_once_the_destination_answers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(reply_for("127.0.0.1", port).await, SOCKS_REPLY_SUCCEEDED);
+        drop(listener);
+        assert_eq!(
+            reply_for("127.0.0.1", port).await,
+            SOCKS_REPLY_CONNECTION_REFUSED
+        );
+    }
+
+    #[tokio::test]
+    async fn an_unknown_name_is_an_unreachable_host() {
+        assert_eq!(
+            reply_for("nothing.invalid", 80).await,
+            SOCKS_REPLY_HOST_UNREACHABLE
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::net::SocketAddr;
+use tauri::AppHandle;
+use tokio::{
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::oneshot,
+};
+
+use super::{error::ProxyError, tunnel, ProxyContext};
+
+const LISTEN_BACKLOG: u32 = 1024;
+
+// ** TPROXY hands over connections addressed to other hosts, the socket has to opt in before bind
+pub fn bind(addr: SocketAddr) -> Result<TcpListener, ProxyError> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    };
+    let socket = match socket {
+        Ok(s) => s,
+        Err(e) => return Err(ProxyError::BindError(e.to_string())),
+    };
+    set_transparent(&socket, addr.is_ipv6())?;
+    if let Err(e) = socket.set_reuseaddr(true) {
+        return Err(ProxyError::BindError(e.to_string()));
+    }
+    if let Err(e) = socket.bind(addr) {
+        return Err(ProxyError::BindError(format!("{}: {}", addr, e)));
+    }
+    match socket.listen(LISTEN_BACKLOG) {
+        Ok(l) => Ok(l),
+        Err(e) => Err(ProxyError::BindError(format!("{}: {}", addr, e))),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_transparent(socket: &TcpSocket, is_v6: bool) -> Result<(), ProxyError> {
+    use std::{io, mem, os::fd::AsRawFd};
+
+    let (level, option) = match is_v6 {
+        true => (libc::SOL_IPV6, libc::IPV6_TRANSPARENT),
+        false => (libc::SOL_IP, libc::IP_TRANSPARENT),
+    };
+    let on: libc::c_int = 1;
+    // * safe: `on` outlives the call and the length is its size
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    // * it takes CAP_NET_ADMIN, REDIRECT keeps working without it
+    if result != 0 {
+        println!(
+            "transparent listener will not receive TPROXY connections: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_transparent(_socket: &TcpSocket, _is_v6: bool) -> Result<(), ProxyError> {
+    Err(ProxyError::BindError(
+        "transparent listeners are only supported on linux".to_string(),
+    ))
+}
+
+// ** accepts connections redirected by iptables (REDIRECT or TPROXY) until `shutdown` fires
+pub async fn serve(
+    listener: TcpListener,
+    context: ProxyContext,
+    app_handle: AppHandle,
+    mut shutdown: oneshot::Receiver<()>,
+) -> Result<(), ProxyError> {
+    let listen_addr = match listener.local_addr() {
+        Ok(a) => a,
+        Err(e) => return Err(ProxyError::ServeError(e.to_string())),
+    };
+    loop {
+        let (stream, client_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(a) => a,
+                Err(e) => return Err(ProxyError::ServeError(e.to_string())),
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        let context = context.clone();
+        let app_handle = app_handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                serve_connection(stream, client_addr, listen_addr, context, app_handle).await
+            {
+                println!("proxy error{}", e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    client_addr: SocketAddr,
+    listen_addr: SocketAddr,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError> {
+    let destination = original_destination(&stream)?;
+    // * a client talking to the listener directly would make us connect to ourselves forever
+    let local = stream.local_addr().ok().map(|a| a.ip().to_canonical());
+    if Some(destination.ip().to_canonical()) == local && destination.port() == listen_addr.port() {
+        return Err(ProxyError::OriginalDestinationError(format!(
+            "connection from {} was not redirected",
+            client_addr
+        )));
+    }
+    let host = destination.ip().to_canonical().to_string();
+    tunnel::serve_sniffed(
+        stream,
+        &host,
+        destination.port(),
+        client_addr,
+        context,
+        app_handle,
+    )
+    .await
+}
+
+// ** REDIRECT keeps the original address in conntrack, TPROXY leaves it as the local address
+#[cfg(target_os = "linux")]
+fn original_destination(stream: &TcpStream) -> Result<SocketAddr, ProxyError> {
+    use std::{
+        mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddrV6},
+        os::fd::AsRawFd,
+    };
+
+    let local = match stream.local_addr() {
+        Ok(a) => a,
+        Err(e) => return Err(ProxyError::OriginalDestinationError(e.to_string())),
+    };
+    let fd = stream.as_raw_fd();
+    // * v4 clients of a dual-stack listener show up with a v4-mapped local address
+    let is_v4 = match local {
+        SocketAddr::V4(_) => true,
+        SocketAddr::V6(a) => a.ip().to_ipv4_mapped().is_some(),
+    };
+    let original = match is_v4 {
+        true => {
+            let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+            let mut len = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            // * safe: `addr` and `len` describe a buffer of the size the option writes
+            let result = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_IP,
+                    libc::SO_ORIGINAL_DST,
+                    &mut addr as *mut _ as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            match result {
+                0 => Some(SocketAddr::from((
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                ))),
+                _ => None,
+            }
+        }
+        false => {
+            let mut addr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            let mut len = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+            let result = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_IPV6,
+                    libc::IP6T_SO_ORIGINAL_DST,
+                    &mut addr as *mut _ as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            match result {
+                0 => Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                ))),
+                _ => None,
+            }
+        }
+    };
+    // * no conntrack entry, the connection either came through TPROXY or was not redirected at all
+    Ok(original.unwrap_or(local))
+}
+
+// * never bound elsewhere, see `set_transparent`
+#[cfg(not(target_os = "linux"))]
+fn original_destination(stream: &TcpStream) -> Result<SocketAddr, ProxyError> {
+    match stream.local_addr() {
+        Ok(a) => Ok(a),
+        Err(e) => Err(ProxyError::OriginalDestinationError(e.to_string())),
+    }
+}
@@ -0,0 +1,297 @@
+use hyper::{
+    header::HOST,
+    http::uri::{Authority, Scheme},
+    server::conn::Http,
+    Body, Request, Response, StatusCode, Uri,
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tauri::AppHandle;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_native_tls::TlsAcceptor;
+
+use super::{error::ProxyError, handle, ClientAddr, ProxyContext};
+use crate::http_util::{stream::PrefixedStream, tls};
+
+const HTTP_DEFAULT_PORT: u16 = 80;
+const HTTPS_DEFAULT_PORT: u16 = 443;
+// * long enough for any request line to start with its method
+const SNIFF_LEN: usize = 8;
+// * protocols where the server speaks first never send anything, they are relayed as they are
+const SNIFF_TIMEOUT_MILLIS: u64 = 1000;
+const HTTP_METHODS: &[&str] = &[
+    "GET ", "POST ", "PUT ", "HEAD ", "DELETE ", "OPTIONS ", "PATCH ", "TRACE ",
+];
+
+// ** answer CONNECT with 200 and serve the decrypted tunnel in the background
+pub fn handle_connect(
+    request: Request<Body>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Response<Body> {
+    let authority = match request.uri().authority() {
+        Some(a) => a.clone(),
+        None => {
+            let e = ProxyError::InvalidAuthorityError(request.uri().to_string());
+            println!("proxy error{}", e);
+            let mut response = Response::new(Body::from(e.to_string()));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            return response;
+        }
+    };
+    let client_addr = request.extensions().get::<ClientAddr>().copied();
+
+    tokio::spawn(async move {
+        let upgraded = match hyper::upgrade::on(request).await {
+            Ok(u) => u,
+            Err(e) => {
+                println!("proxy error{}", ProxyError::UpgradeError(e.to_string()));
+                return;
+            }
+        };
+        if let Err(e) = serve_tunnel(upgraded, authority, client_addr, context, app_handle).await {
+            println!("proxy error{}", e);
+        }
+    });
+
+    Response::new(Body::empty())
+}
+
+// ** a raw connection to `host:port`, socks or redirected, served by what its first bytes look like
+pub async fn serve_sniffed(
+    stream: TcpStream,
+    host: &str,
+    port: u16,
+    client_addr: SocketAddr,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError> {
+    let authority = match host.contains(':') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    };
+    let authority = match authority.parse::<Authority>() {
+        Ok(a) => a,
+        Err(e) => return Err(ProxyError::InvalidAuthorityError(e.to_string())),
+    };
+    let client_addr = Some(ClientAddr(client_addr));
+
+    let mut head = [0u8; SNIFF_LEN];
+    let sniff = tokio::time::timeout(
+        Duration::from_millis(SNIFF_TIMEOUT_MILLIS),
+        stream.peek(&mut head),
+    )
+    .await;
+    let head = match sniff {
+        Ok(Ok(n)) => &head[..n],
+        Ok(Err(e)) => return Err(ProxyError::TunnelServeError(e.to_string())),
+        Err(_) => &head[..0],
+    };
+    if head.first() == Some(&tls::TLS_HANDSHAKE_RECORD) {
+        serve_tunnel(stream, authority, client_addr, context, app_handle).await
+    } else if HTTP_METHODS.iter().any(|m| head.starts_with(m.as_bytes())) {
+        serve_plain(stream, authority, client_addr, context, app_handle).await
+    } else {
+        relay(stream, host, port).await
+    }
+}
+
+// ** any stream that starts with a tls handshake, a CONNECT tunnel or a socks connection
+pub async fn serve_tunnel<S>(
+    mut stream: S,
+    authority: Authority,
+    client_addr: Option<ClientAddr>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // ** prefer the name the client asks for in its ClientHello, fall back to the CONNECT target
+    let client_hello = match tls::read_client_hello(&mut stream).await {
+        Ok(b) => b,
+        Err(e) => return Err(ProxyError::TlsAcceptError(e.to_string())),
+    };
+    let sni = tls::parse_sni(&client_hello);
+    let host = match &sni {
+        Some(sni) => sni.clone(),
+        None => authority.host().to_string(),
+    };
+    // * a bare ip target (socks, redirected traffic) gets the sni name, so the upstream tls can verify it
+    let authority = match sni {
+        Some(sni) if is_ip(authority.host()) => {
+            match format!(
+                "{}:{}",
+                sni,
+                authority.port_u16().unwrap_or(HTTPS_DEFAULT_PORT)
+            )
+            .parse::<Authority>()
+            {
+                Ok(a) => a,
+                Err(_) => authority,
+            }
+        }
+        _ => authority,
+    };
+    let identity = match context.ca.read().unwrap().certificate_for(&host) {
+        Ok(i) => i,
+        Err(e) => return Err(ProxyError::TlsSetupError(e.to_string())),
+    };
+    // * h2 is offered the way a real server would, the upstream side picks its own version
+    let acceptor = match native_tls::TlsAcceptor::builder(identity)
+        .accept_alpn(&["h2", "http/1.1"])
+        .build()
+    {
+        Ok(a) => TlsAcceptor::from(a),
+        Err(e) => return Err(ProxyError::TlsSetupError(e.to_string())),
+    };
+    let stream = match acceptor
+        .accept(PrefixedStream::new(client_hello, stream))
+        .await
+    {
+        Ok(s) => s,
+        Err(e) => return Err(ProxyError::TlsAcceptError(e.to_string())),
+    };
+    let h2 = matches!(stream.get_ref().negotiated_alpn(), Ok(Some(p)) if p == b"h2");
+    serve_origin_form(
+        stream,
+        Scheme::HTTPS,
+        h2,
+        authority,
+        client_addr,
+        context,
+        app_handle,
+    )
+    .await
+}
+
+// ** plain http on a stream whose destination is already known
+pub async fn serve_plain<S>(
+    stream: S,
+    authority: Authority,
+    client_addr: Option<ClientAddr>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    serve_origin_form(
+        stream,
+        Scheme::HTTP,
+        false,
+        authority,
+        client_addr,
+        context,
+        app_handle,
+    )
+    .await
+}
+
+async fn serve_origin_form<S>(
+    stream: S,
+    scheme: Scheme,
+    h2: bool,
+    authority: Authority,
+    client_addr: Option<ClientAddr>,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |request: Request<Body>| {
+        let context = context.clone();
+        let app_handle = app_handle.clone();
+        let scheme = scheme.clone();
+        // * without tls there is no sni, the Host header names the site when the target is a bare ip
+        let authority = match scheme == Scheme::HTTP {
+            true => host_authority(&request).unwrap_or_else(|| authority.clone()),
+            false => authority.clone(),
+        };
+        async move {
+            let mut request = match to_absolute_form(request, &scheme, &authority) {
+                Ok(rq) => rq,
+                Err(e) => {
+                    let mut response = Response::new(Body::from(e.to_string()));
+                    *response.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok::<_, Infallible>(response);
+                }
+            };
+            if let Some(client_addr) = client_addr {
+                request.extensions_mut().insert(client_addr);
+            }
+            Ok::<_, Infallible>(handle(request, context, app_handle).await)
+        }
+    });
+
+    match Http::new()
+        .http2_only(h2)
+        .serve_connection(stream, service)
+        .with_upgrades()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ProxyError::TunnelServeError(e.to_string())),
+    }
+}
+
+// ** requests inside the tunnel are origin-form (`/path`), but the upstream client needs the full url
+fn to_absolute_form(
+    request: Request<Body>,
+    scheme: &Scheme,
+    authority: &Authority,
+) -> Result<Request<Body>, ProxyError> {
+    let (mut parts, body) = request.into_parts();
+    let default_port = match *scheme == Scheme::HTTPS {
+        true => HTTPS_DEFAULT_PORT,
+        false => HTTP_DEFAULT_PORT,
+    };
+    let authority = match authority.port_u16() {
+        Some(p) if p == default_port => authority.host().to_string(),
+        _ => authority.to_string(),
+    };
+    let path_and_query = match parts.uri.path_and_query() {
+        Some(pq) => pq.as_str().to_string(),
+        None => "/".to_string(),
+    };
+    parts.uri = match Uri::builder()
+        .scheme(scheme.clone())
+        .authority(authority.as_str())
+        .path_and_query(path_and_query.as_str())
+        .build()
+    {
+        Ok(u) => u,
+        Err(e) => return Err(ProxyError::InvalidAuthorityError(e.to_string())),
+    };
+    Ok(Request::from_parts(parts, body))
+}
+
+// ** anything that is neither tls nor http goes to the destination untouched and unrecorded
+async fn relay(mut stream: TcpStream, host: &str, port: u16) -> Result<(), ProxyError> {
+    let mut upstream = match TcpStream::connect((host, port)).await {
+        Ok(s) => s,
+        Err(e) => return Err(ProxyError::RelayError(format!("{}:{}: {}", host, port, e))),
+    };
+    match tokio::io::copy_bidirectional(&mut stream, &mut upstream).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ProxyError::RelayError(e.to_string())),
+    }
+}
+
+fn is_ip(host: &str) -> bool {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok()
+}
+
+fn host_authority(request: &Request<Body>) -> Option<Authority> {
+    request.headers().get(HOST)?.to_str().ok()?.parse().ok()
+}
@@ -0,0 +1,455 @@
+use hyper::{
+    client::{
+        connect::{Connected, Connection, HttpConnector, HttpInfo},
+        Client,
+    },
+    header::{HeaderValue, COOKIE, UPGRADE},
+    http::uri::Scheme,
+    service::Service,
+    Body, Request, Response, Uri, Version,
+};
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+use super::chain::{ProxyRouter, UpstreamProxy, UpstreamProxyRule};
+use super::error::ProxyError;
+
+const UPSTREAM_TIMEOUT_SECS: u64 = 60;
+const HTTP_DEFAULT_PORT: u16 = 80;
+const HTTPS_DEFAULT_PORT: u16 = 443;
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+// ** h2 is only ever offered over tls, a server that does not take it gets http/1.1
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpPreference {
+    // * offers h2 when the client spoke it, so each side keeps the version it chose
+    #[default]
+    MatchClient,
+    Http1,
+    Http2,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UpstreamConfig {
+    // * idle keep-alive connections kept per host
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    // * `None` keeps idle connections until the server closes them
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub http_preference: HttpPreference,
+    // * ordered, see ProxyRouter
+    #[serde(default)]
+    pub proxies: Vec<UpstreamProxyRule>,
+    // * host globs that always connect directly
+    #[serde(default)]
+    pub bypass: Vec<String>,
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    DEFAULT_POOL_MAX_IDLE_PER_HOST
+}
+
+fn default_pool_idle_timeout_secs() -> Option<u64> {
+    Some(DEFAULT_POOL_IDLE_TIMEOUT_SECS)
+}
+
+fn default_connect_timeout_secs() -> Option<u64> {
+    Some(DEFAULT_CONNECT_TIMEOUT_SECS)
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            http_preference: HttpPreference::default(),
+            proxies: Vec::new(),
+            bypass: Vec::new(),
+        }
+    }
+}
+
+// ** how a response reached us
+pub struct UpstreamConnection {
+    pub remote_addr: Option<String>,
+    // * false for the first exchange on a freshly opened connection
+    pub reused: bool,
+}
+
+// ** long-lived clients for every handler, so keep-alive and the pools survive between requests
+pub struct UpstreamClient {
+    http1: Client<UpstreamConnector, Body>,
+    // * offers h2 in alpn, kept apart so an http/1.1 request never lands on an h2 connection by accident
+    http2: Client<UpstreamConnector, Body>,
+    preference: HttpPreference,
+    router: Arc<ProxyRouter>,
+    connect_timeout: Option<Duration>,
+}
+
+impl UpstreamClient {
+    pub fn new(config: &UpstreamConfig) -> Result<Self, ProxyError> {
+        let router = ProxyRouter::new(&config.proxies, &config.bypass)?;
+        Self::build(config, router)
+    }
+
+    // * every destination through `proxy`, used to try a proxy out before it is saved
+    pub fn through(config: &UpstreamConfig, proxy: UpstreamProxy) -> Result<Self, ProxyError> {
+        Self::build(config, ProxyRouter::single(proxy))
+    }
+
+    fn build(config: &UpstreamConfig, router: ProxyRouter) -> Result<Self, ProxyError> {
+        let router = Arc::new(router);
+        Ok(UpstreamClient {
+            http1: Self::client(config, router.clone(), false)?,
+            http2: Self::client(config, router.clone(), true)?,
+            preference: config.http_preference,
+            router,
+            connect_timeout: config.connect_timeout_secs.map(Duration::from_secs),
+        })
+    }
+
+    fn client(
+        config: &UpstreamConfig,
+        router: Arc<ProxyRouter>,
+        offer_h2: bool,
+    ) -> Result<Client<UpstreamConnector, Body>, ProxyError> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(config.connect_timeout_secs.map(Duration::from_secs));
+
+        let mut tls = native_tls::TlsConnector::builder();
+        if offer_h2 {
+            tls.request_alpns(&["h2", "http/1.1"]);
+        }
+        let tls = match tls.build() {
+            Ok(t) => t,
+            Err(e) => return Err(ProxyError::UpstreamSetupError(e.to_string())),
+        };
+        let tls: tokio_native_tls::TlsConnector = tls.into();
+        let connector = UpstreamConnector {
+            https: HttpsConnector::from((http.clone(), tls.clone())),
+            http,
+            tls,
+            router,
+            connect_timeout: config.connect_timeout_secs.map(Duration::from_secs),
+        };
+
+        Ok(Client::builder()
+            .pool_max_idle_per_host(config.pool_max_idle_per_host)
+            .pool_idle_timeout(config.pool_idle_timeout_secs.map(Duration::from_secs))
+            .build(connector))
+    }
+
+    // ** the response keeps the version the upstream answered with, the caller translates it for its client
+    pub async fn send(
+        &self,
+        request: Request<Body>,
+    ) -> Result<(Response<Body>, UpstreamConnection), ProxyError> {
+        // * an upgrade only exists in http/1.1, h2 would strip its headers
+        let offer_h2 = !request.headers().contains_key(UPGRADE)
+            && match self.preference {
+                HttpPreference::MatchClient => request.version() == Version::HTTP_2,
+                HttpPreference::Http1 => false,
+                HttpPreference::Http2 => true,
+            };
+        let client = match offer_h2 {
+            true => &self.http2,
+            false => &self.http1,
+        };
+        let request = to_upstream_version(request);
+        let timeout = Duration::from_secs(UPSTREAM_TIMEOUT_SECS);
+        let response = match tokio::time::timeout(timeout, client.request(request)).await {
+            Ok(Ok(rs)) => rs,
+            Ok(Err(e)) => return Err(upstream_error(e)),
+            Err(_) => {
+                return Err(ProxyError::UpstreamTimeoutError(format!(
+                    "no response after {} seconds",
+                    UPSTREAM_TIMEOUT_SECS
+                )))
+            }
+        };
+        let remote_addr = response
+            .extensions()
+            .get::<HttpInfo>()
+            .map(|i| i.remote_addr().to_string());
+        let reused = match response.extensions().get::<ConnectionTag>() {
+            Some(t) => t.uses.fetch_add(1, Ordering::Relaxed) > 0,
+            None => false,
+        };
+        Ok((
+            response,
+            UpstreamConnection {
+                remote_addr,
+                reused,
+            },
+        ))
+    }
+
+    // ** a raw connection to `host:port`, through the proxy the router picks for it
+    // ** a failing proxy is reported as `Other`, so it is not mistaken for the destination refusing
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let connecting = async {
+            match self.router.route(host) {
+                Some(proxy) => connect_through(proxy, host, port).await,
+                None => connect_direct(host, port).await,
+            }
+        };
+        match self.connect_timeout {
+            Some(t) => match tokio::time::timeout(t, connecting).await {
+                Ok(r) => r,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{}:{} did not connect in time", host, port),
+                )),
+            },
+            None => connecting.await,
+        }
+    }
+}
+
+// * a name that does not resolve is an unreachable host, not a generic failure
+async fn connect_direct(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addrs = match tokio::net::lookup_host((host, port)).await {
+        Ok(a) => a,
+        Err(e) => {
+            return Err(io::Error::new(
+                io::ErrorKind::HostUnreachable,
+                format!("{}: {}", host, e),
+            ))
+        }
+    };
+    let mut last_error = io::Error::new(
+        io::ErrorKind::HostUnreachable,
+        format!("{} has no address", host),
+    );
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(s) => return Ok(s),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+async fn connect_through(proxy: &UpstreamProxy, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = match TcpStream::connect((proxy.host.as_str(), proxy.port)).await {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(io::Error::other(format!(
+                "proxy {}:{}: {}",
+                proxy.host, proxy.port, e
+            )))
+        }
+    };
+    match proxy.open_tunnel(&mut stream, host, port).await {
+        Ok(_) => Ok(stream),
+        Err(e) => Err(io::Error::other(e.to_string())),
+    }
+}
+
+// ** hyper refuses an h2 request on an http/1.1 connection, as 1.1 it goes out as whatever alpn settled on
+// ** the http/1 encoder panics on any version it does not write itself, h3 and 0.9 from the frontend included
+fn to_upstream_version(mut request: Request<Body>) -> Request<Body> {
+    if matches!(request.version(), Version::HTTP_10 | Version::HTTP_11) {
+        return request;
+    }
+    *request.version_mut() = Version::HTTP_11;
+    // * h2 may split cookies over several fields, http/1.1 wants them in one
+    let cookies: Vec<String> = request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .collect();
+    if cookies.len() > 1 {
+        if let Ok(v) = HeaderValue::from_str(&cookies.join("; ")) {
+            request.headers_mut().insert(COOKIE, v);
+        }
+    }
+    request
+}
+
+fn upstream_error(e: hyper::Error) -> ProxyError {
+    if e.is_timeout() {
+        return ProxyError::UpstreamTimeoutError(e.to_string());
+    }
+    // * hyper's display hides the cause (dns failure, connection refused, ...)
+    match std::error::Error::source(&e) {
+        Some(cause) => ProxyError::UpstreamError(format!("{}: {}", e, cause)),
+        None => ProxyError::UpstreamError(e.to_string()),
+    }
+}
+
+// ** hyper copies connection extras into every response, the shared counter tells a reused connection apart
+#[derive(Clone)]
+struct ConnectionTag {
+    uses: Arc<AtomicU64>,
+}
+
+// ** direct connections go through hyper-tls, proxied ones are tunneled first and get tls on top
+#[derive(Clone)]
+struct UpstreamConnector {
+    https: HttpsConnector<HttpConnector>,
+    http: HttpConnector,
+    tls: tokio_native_tls::TlsConnector,
+    router: Arc<ProxyRouter>,
+    // * the proxy handshake counts as part of connecting
+    connect_timeout: Option<Duration>,
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = UpstreamStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.https.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = uri
+            .host()
+            .unwrap_or("")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let proxy = match self.router.route(&host) {
+            Some(p) => p.clone(),
+            None => {
+                let connecting = self.https.call(uri);
+                return Box::pin(async move { Ok(UpstreamStream::new(connecting.await?)) });
+            }
+        };
+
+        let mut http = self.http.clone();
+        let tls = self.tls.clone();
+        let connect_timeout = self.connect_timeout;
+        Box::pin(async move {
+            let is_https = uri.scheme() == Some(&Scheme::HTTPS);
+            let port = match uri.port_u16() {
+                Some(p) => p,
+                None if is_https => HTTPS_DEFAULT_PORT,
+                None => HTTP_DEFAULT_PORT,
+            };
+            let proxy_host = match proxy.host.contains(':') {
+                true => format!("[{}]", proxy.host),
+                false => proxy.host.clone(),
+            };
+            let proxy_uri = format!("http://{}:{}", proxy_host, proxy.port).parse::<Uri>()?;
+            let mut tcp = http.call(proxy_uri).await?;
+            let tunnel = proxy.open_tunnel(&mut tcp, &host, port);
+            // * a proxy that accepts the connection but never answers would hold the request forever
+            match connect_timeout {
+                Some(t) => match tokio::time::timeout(t, tunnel).await {
+                    Ok(r) => r?,
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "proxy {}:{} did not open a tunnel in time",
+                                proxy_host, proxy.port
+                            ),
+                        )
+                        .into())
+                    }
+                },
+                None => tunnel.await?,
+            }
+            let stream = if is_https {
+                MaybeHttpsStream::Https(tls.connect(&host, tcp).await?)
+            } else {
+                MaybeHttpsStream::Http(tcp)
+            };
+            Ok(UpstreamStream::new(stream))
+        })
+    }
+}
+
+struct UpstreamStream {
+    inner: MaybeHttpsStream<TcpStream>,
+    h2: bool,
+    tag: ConnectionTag,
+}
+
+impl UpstreamStream {
+    fn new(inner: MaybeHttpsStream<TcpStream>) -> Self {
+        // * hyper-tls does not report alpn itself
+        let h2 = match &inner {
+            MaybeHttpsStream::Https(s) => {
+                matches!(s.get_ref().negotiated_alpn(), Ok(Some(p)) if p == b"h2")
+            }
+            MaybeHttpsStream::Http(_) => false,
+        };
+        UpstreamStream {
+            inner,
+            h2,
+            tag: ConnectionTag {
+                uses: Arc::new(AtomicU64::new(0)),
+            },
+        }
+    }
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected().extra(self.tag.clone());
+        if self.h2 {
+            connected.negotiated_h2()
+        } else {
+            connected
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
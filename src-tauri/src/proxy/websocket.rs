@@ -0,0 +1,350 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{SinkExt, StreamExt};
+use hyper::{
+    header::{CONNECTION, UPGRADE},
+    upgrade::{OnUpgrade, Upgraded},
+    HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use std::{io::ErrorKind, sync::Arc};
+use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        error::ProtocolError,
+        protocol::{frame::coding::CloseCode, CloseFrame, Role},
+        Message,
+    },
+    WebSocketStream,
+};
+
+use super::{error::ProxyError, pilot_state, ProxyContext};
+use crate::history::{now_millis, HistoryStore, RecordedFrame};
+use crate::http_util::intercept::{InterceptAction, InterceptKind};
+
+const WEBSOCKET_FRAME_EVENT: &str = "websocket-frame";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameOpcode {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+}
+
+// ** one websocket message, fragmented ones are recorded once they are reassembled
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FrameForFront {
+    // * the pair id of the upgrade handshake
+    pub pair_id: String,
+    pub direction: FrameDirection,
+    pub opcode: FrameOpcode,
+    // * text frames as they are, anything else base64, a close frame is its code and reason
+    pub payload: String,
+    // * unix milliseconds
+    pub timestamp: i64,
+    #[serde(default)]
+    pub edited: bool,
+    #[serde(default)]
+    pub dropped: bool,
+}
+
+// ** what the handshake decided for the frames that follow it
+#[derive(Clone)]
+pub struct FramePolicy {
+    pub pair_id: String,
+    pub record: bool,
+    // * the project the handshake was recorded in
+    pub history: Arc<HistoryStore>,
+    // * the intercept rules matched the handshake request / response, the pilot is checked per frame
+    pub hold_to_server: bool,
+    pub hold_to_client: bool,
+}
+
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    let upgrade = headers
+        .get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let connection = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case("upgrade"));
+    upgrade && connection
+}
+
+// ** runs until either side closes, the handshake has to have been answered with 101 on both ends
+pub async fn bridge(
+    client: OnUpgrade,
+    server: OnUpgrade,
+    policy: FramePolicy,
+    context: ProxyContext,
+    app_handle: AppHandle,
+) -> Result<(), ProxyError> {
+    let (client, server) = match tokio::try_join!(client, server) {
+        Ok(u) => u,
+        Err(e) => return Err(ProxyError::UpgradeError(e.to_string())),
+    };
+    let client = WebSocketStream::from_raw_socket(client, Role::Server, None).await;
+    let server = WebSocketStream::from_raw_socket(server, Role::Client, None).await;
+    let (client_sink, client_stream) = client.split();
+    let (server_sink, server_stream) = server.split();
+
+    // * written in order on a task of their own, so sqlite never stalls the pumps
+    let recorder = match policy.record {
+        true => {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<FrameForFront>();
+            let history = policy.history.clone();
+            let app_handle = app_handle.clone();
+            tokio::spawn(async move {
+                while let Some(frame) = receiver.recv().await {
+                    frame.record(&history, &app_handle).await;
+                }
+            });
+            Some(sender)
+        }
+        false => None,
+    };
+
+    let to_server = pump(
+        client_stream,
+        server_sink,
+        FrameDirection::ClientToServer,
+        &policy,
+        recorder.clone(),
+        &context,
+        &app_handle,
+    );
+    let to_client = pump(
+        server_stream,
+        client_sink,
+        FrameDirection::ServerToClient,
+        &policy,
+        recorder,
+        &context,
+        &app_handle,
+    );
+    // * once one side is gone the other has nobody to talk to
+    tokio::select! {
+        r = to_server => r,
+        r = to_client => r,
+    }
+}
+
+async fn pump(
+    mut from: futures::stream::SplitStream<WebSocketStream<Upgraded>>,
+    mut to: futures::stream::SplitSink<WebSocketStream<Upgraded>, Message>,
+    direction: FrameDirection,
+    policy: &FramePolicy,
+    recorder: Option<UnboundedSender<FrameForFront>>,
+    context: &ProxyContext,
+    app_handle: &AppHandle,
+) -> Result<(), ProxyError> {
+    let hold = match direction {
+        FrameDirection::ClientToServer => policy.hold_to_server,
+        FrameDirection::ServerToClient => policy.hold_to_client,
+    };
+    while let Some(message) = from.next().await {
+        let message = match message {
+            Ok(m) => m,
+            Err(e) => return closed_or(e),
+        };
+        let mut frame = match FrameForFront::from_message(&message, &policy.pair_id, direction) {
+            Some(f) => f,
+            None => continue,
+        };
+        let is_data = matches!(frame.opcode, FrameOpcode::Text | FrameOpcode::Binary);
+        let message = if hold && is_data && pilot_state(context.pilot_state.clone()) {
+            match hold_frame(&frame, context, app_handle).await? {
+                Some(edited) => {
+                    let m = edited.to_message()?;
+                    frame = edited;
+                    Some(m)
+                }
+                None => {
+                    frame.dropped = true;
+                    None
+                }
+            }
+        } else {
+            Some(message)
+        };
+        if let Some(recorder) = &recorder {
+            let _ = recorder.send(frame);
+        }
+        if let Some(message) = message {
+            if let Err(e) = to.send(message).await {
+                return closed_or(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// * `None` drops the frame
+async fn hold_frame(
+    frame: &FrameForFront,
+    context: &ProxyContext,
+    app_handle: &AppHandle,
+) -> Result<Option<FrameForFront>, ProxyError> {
+    let payload = match serde_json::to_string(frame) {
+        Ok(p) => p,
+        Err(e) => return Err(ProxyError::FrameInterceptError(e.to_string())),
+    };
+    let kind = match frame.direction {
+        FrameDirection::ClientToServer => InterceptKind::ClientMessage,
+        FrameDirection::ServerToClient => InterceptKind::ServerMessage,
+    };
+    let timeout = context.config.read().unwrap().intercept_timeout();
+    let decision = match context
+        .intercept
+        .wait(app_handle, &frame.pair_id, kind, payload, timeout)
+        .await
+    {
+        Ok(d) => d,
+        Err(e) => return Err(ProxyError::FrameInterceptError(e.to_string())),
+    };
+    match decision.action {
+        InterceptAction::Forward => Ok(Some(frame.clone())),
+        InterceptAction::ForwardModified { payload } => {
+            let mut edited = match serde_json::from_str::<FrameForFront>(&payload) {
+                Ok(f) => f,
+                Err(e) => return Err(ProxyError::FrameInterceptError(e.to_string())),
+            };
+            // * only the content can be changed from the pilot
+            edited.pair_id = frame.pair_id.clone();
+            edited.direction = frame.direction;
+            edited.timestamp = frame.timestamp;
+            edited.edited = edited.opcode != frame.opcode || edited.payload != frame.payload;
+            edited.dropped = false;
+            Ok(Some(edited))
+        }
+        InterceptAction::Drop { .. } => Ok(None),
+    }
+}
+
+// * a peer going away mid-conversation is how websockets usually end
+fn closed_or(e: tungstenite::Error) -> Result<(), ProxyError> {
+    match e {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Ok(()),
+        tungstenite::Error::Protocol(
+            ProtocolError::ResetWithoutClosingHandshake | ProtocolError::SendAfterClosing,
+        ) => Ok(()),
+        tungstenite::Error::Io(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof
+            ) =>
+        {
+            Ok(())
+        }
+        e => Err(ProxyError::WebSocketError(e.to_string())),
+    }
+}
+
+impl FrameForFront {
+    // * raw frames only show up when writing, they are never read
+    fn from_message(message: &Message, pair_id: &str, direction: FrameDirection) -> Option<Self> {
+        let (opcode, payload) = match message {
+            Message::Text(t) => (FrameOpcode::Text, t.clone()),
+            Message::Binary(b) => (FrameOpcode::Binary, STANDARD.encode(b)),
+            Message::Ping(b) => (FrameOpcode::Ping, STANDARD.encode(b)),
+            Message::Pong(b) => (FrameOpcode::Pong, STANDARD.encode(b)),
+            Message::Close(Some(c)) => (
+                FrameOpcode::Close,
+                format!("{} {}", u16::from(c.code), c.reason),
+            ),
+            Message::Close(None) => (FrameOpcode::Close, String::new()),
+            Message::Frame(_) => return None,
+        };
+        Some(FrameForFront {
+            pair_id: pair_id.to_string(),
+            direction,
+            opcode,
+            payload,
+            timestamp: now_millis(),
+            edited: false,
+            dropped: false,
+        })
+    }
+
+    fn to_message(&self) -> Result<Message, ProxyError> {
+        let message = match self.opcode {
+            FrameOpcode::Text => Message::Text(self.payload.clone()),
+            FrameOpcode::Binary => Message::Binary(self.decoded()?),
+            FrameOpcode::Ping => Message::Ping(self.decoded()?),
+            FrameOpcode::Pong => Message::Pong(self.decoded()?),
+            FrameOpcode::Close => {
+                let (code, reason) = match self.payload.split_once(' ') {
+                    Some((c, r)) => (c, r),
+                    None => (self.payload.as_str(), ""),
+                };
+                match code.parse::<u16>() {
+                    Ok(code) => Message::Close(Some(CloseFrame {
+                        code: CloseCode::from(code),
+                        reason: reason.to_string().into(),
+                    })),
+                    Err(_) => Message::Close(None),
+                }
+            }
+        };
+        Ok(message)
+    }
+
+    fn decoded(&self) -> Result<Vec<u8>, ProxyError> {
+        match STANDARD.decode(&self.payload) {
+            Ok(b) => Ok(b),
+            Err(e) => Err(ProxyError::FrameInterceptError(e.to_string())),
+        }
+    }
+
+    // ** history failures are logged, they never break the connection itself
+    async fn record(&self, history: &Arc<HistoryStore>, app_handle: &AppHandle) {
+        let frame = match serde_json::to_string(self) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("failed to serialize websocket frame: {}", e);
+                return;
+            }
+        };
+        let direction = match self.direction {
+            FrameDirection::ClientToServer => "client_to_server",
+            FrameDirection::ServerToClient => "server_to_client",
+        };
+        let opcode = match self.opcode {
+            FrameOpcode::Text => "text",
+            FrameOpcode::Binary => "binary",
+            FrameOpcode::Ping => "ping",
+            FrameOpcode::Pong => "pong",
+            FrameOpcode::Close => "close",
+        };
+        let (pair_id, json, timestamp) = (self.pair_id.clone(), frame.clone(), self.timestamp);
+        let result = history.clone().run_blocking(move |h| {
+            h.record_frame(&RecordedFrame {
+                pair_id: &pair_id,
+                direction,
+                opcode,
+                frame: &json,
+                timestamp,
+            })
+        });
+        if let Err(e) = result.await {
+            println!("proxy error{}", e);
+        }
+        if let Err(e) = app_handle.emit_all(WEBSOCKET_FRAME_EVENT, frame) {
+            println!("failed to emit websocket frame: {}", e);
+        }
+    }
+}
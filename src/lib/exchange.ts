@@ -6,6 +6,7 @@ export class Request {
     public method: string;
     public url: string;
     public body: string;
+    public body_encoding: BodyEncoding;
     public pair_id: string;
     public is_empty: boolean;
 
@@ -15,6 +16,7 @@ export class Request {
         this.method = args.method;
         this.url = args.url;
         this.body = args.body;
+        this.body_encoding = args.body_encoding ?? "utf8";
         this.to_editable();
 
         if (empty !== undefined) {
@@ -23,15 +25,15 @@ export class Request {
             this.is_empty = false;
         }
 
-        let headers: Record<string, string> = JSON.parse(this.headers);
-        this.pair_id = headers["pair-id"];
+        let headers: HeaderEntry[] = JSON.parse(this.headers);
+        this.pair_id = header_value(headers, "pair-id") ?? "";
     }
 
     public to_editable(): string {
         let rq = "";
-        let headers: Record<string, string> = JSON.parse(this.headers);
+        let headers: HeaderEntry[] = JSON.parse(this.headers);
         // method path version
-        let host = headers['host'];
+        let host = header_value(headers, "host");
         let path = "/"
         console.log(this.url);
         if (host !== undefined) {
@@ -44,7 +46,7 @@ export class Request {
 
         // body
         rq += "\n"
-        rq += this.body;
+        rq += body_to_editable(this.body, this.body_encoding);
 
         return rq;
     }
@@ -103,11 +105,12 @@ export function to_request(req_str: string) {
 
 export function empty_request(): Request {
     return new Request({
-        headers: "{}",
+        headers: "[]",
         version: "",
         method: "",
         url: "",
         body: "empty request",
+        body_encoding: "utf8",
     }, true);
 }
 
@@ -116,6 +119,7 @@ export class Response {
     public version: string;
     public status: string;
     public body: string;
+    public body_encoding: BodyEncoding;
     public pair_id: string;
     public is_empty: boolean;
 
@@ -124,6 +128,7 @@ export class Response {
         this.version = args.version;
         this.status = args.status.toString();
         this.body = args.body;
+        this.body_encoding = args.body_encoding ?? "utf8";
 
         if (empty !== undefined) {
             this.is_empty = empty;
@@ -132,13 +137,13 @@ export class Response {
         }
 
 
-        let headers: Record<string, string> = JSON.parse(this.headers);
-        this.pair_id = headers["pair-id"];
+        let headers: HeaderEntry[] = JSON.parse(this.headers);
+        this.pair_id = header_value(headers, "pair-id") ?? "";
     }
 
     public to_editable(): string {
         let rs = "";
-        let headers: Record<string, string> = JSON.parse(this.headers);
+        let headers: HeaderEntry[] = JSON.parse(this.headers);
 
         // version, status
         rs += `${this.version} ${this.status}\n`;
@@ -148,7 +153,7 @@ export class Response {
 
         //body
         rs += "\n"
-        rs += this.body;
+        rs += body_to_editable(this.body, this.body_encoding);
 
         return rs;
     }
@@ -156,35 +161,58 @@ export class Response {
 
 export function empty_response(): Response {
     return new Response({
-        headers: "{}",
+        headers: "[]",
         status: 0,
         version: "",
         body: "empty response",
+        body_encoding: "utf8",
     }, true);
 }
 
-function headers_to_editable(headers: Record<string, string>): string {
+// binary bodies arrive as base64 and are shown as such, with a marker line
+function body_to_editable(body: string, encoding: BodyEncoding): string {
+    if (encoding === "base64") {
+        return `[base64]\n${body}`;
+    }
+    return body;
+}
+
+function header_value(headers: HeaderEntry[], name: string): string | undefined {
+    return headers.find(h => h.name.toLowerCase() === name)?.value;
+}
+
+function headers_to_editable(headers: HeaderEntry[]): string {
     let h = "";
-    for (const key in headers) {
-        let parts = key.split("-");
+    for (const header of headers) {
+        let parts = header.name.split("-");
         let upper_parts: string[] = []
         parts.forEach(part => {
             upper_parts.push(capitalize(part));
         });
         let header_name = upper_parts.join("-");
         if (header_name !== "Pair-Id") {
-            h += `${header_name}: ${headers[key]}\n`;
+            h += `${header_name}: ${header.value}\n`;
         }
     }
     return h;
 }
 
+export type BodyEncoding = "utf8" | "base64";
+
+// headers travel as an ordered list so repeated names (set-cookie, ...) are kept
+export interface HeaderEntry {
+    name: string;
+    value: string;
+    value_encoding?: BodyEncoding;
+}
+
 export interface RustRequest {
     headers: string;
     version: string;
     method: string;
     url: string;
     body: string;
+    body_encoding?: BodyEncoding;
 }
 
 export interface RustResponse {
@@ -192,4 +220,5 @@ export interface RustResponse {
     version: string;
     status: number;
     body: string;
+    body_encoding?: BodyEncoding;
 }
\ No newline at end of file